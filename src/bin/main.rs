@@ -1,63 +1,121 @@
-use std::io::{Read, Write};
-use std::net::{Shutdown, TcpListener};
-use std::thread;
+use std::env;
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use telnet_server::tcp::{create_tcp_server, TcpServerConfig, TcpStreamHandler};
 use telnet_server::telnet::TelnetSession;
 
 const BIND_ADDRESS: &str = "127.0.0.1:9000";
-const MAX_MESSAGE_SIZE: usize = 4096;
-
-fn main() -> std::io::Result<()> {
-    let listener = TcpListener::bind(BIND_ADDRESS)?;
-
-    for stream in listener.incoming() {
-        thread::spawn(move || {
-            let mut stream = match stream {
-                Ok(s) => s,
-                Err(_) => {
-                    /* Stream not available. Just drop this client. */
-                    return;
-                }
-            };
-
-            let mut telnet_session = TelnetSession::create(true);
-            let mut buffer: [u8; MAX_MESSAGE_SIZE] = [0; MAX_MESSAGE_SIZE];
-            let mut response = vec![];
-
-            loop {
-                /* Try loading next client message / command */
-                let read_bytes = match stream.read(&mut buffer) {
-                    Ok(0) => {
-                        /* Connection closed. Shutdown may fail but we'll ignore that as
-                         * the client is dropped anyway. */
-                        stream.shutdown(Shutdown::Both).unwrap_or_default();
-                        return;
-                    }
-                    Ok(c) => c,
-                    Err(_) => {
-                        /* Stream not available. Just drop this client. */
-                        return;
-                    }
-                };
-
-                response.clear();
-
-                if let Some(telnet_data) = telnet_session.accept_data(&buffer[..read_bytes]) {
-                    response.extend_from_slice(telnet_data.as_slice());
-                }
-
-                if let Some(message_response) = generate_message_response(&mut telnet_session) {
-                    response.extend_from_slice(message_response.as_slice());
-                }
-
-                if !response.is_empty() && stream.write_all(response.as_slice()).is_err() {
-                    /* Stream not available. Just drop this client. */
-                    return;
-                }
-            }
-        });
+/// Environment variable that overrides `BIND_ADDRESS`
+const BIND_ADDRESS_VARIABLE: &str = "TELNET_BIND_ADDR";
+const USAGE: &str = "Usage: telnet_server [--bind ADDRESS:PORT]";
+
+fn main() -> ExitCode {
+    /* Command line argument takes precedence over the environment variable */
+    let bind_address = match parse_bind_address(env::args().skip(1)) {
+        Ok(Some(address)) => address.to_string(),
+        Ok(None) => env::var(BIND_ADDRESS_VARIABLE).unwrap_or_else(|_| BIND_ADDRESS.to_string()),
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = create_tcp_server(TcpServerConfig::new(&bind_address), |_| {
+        TelnetStreamHandler {
+            telnet_session: TelnetSession::create(true),
+        }
+    });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Server on {bind_address} failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parses the bind address from given command line arguments
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, without the program name
+///
+/// # Returns
+///
+/// The address given by `--bind`, `None` if it's missing or an error message if the
+/// arguments are malformed.
+fn parse_bind_address<I: Iterator<Item = String>>(
+    mut args: I,
+) -> Result<Option<SocketAddr>, String> {
+    let mut bind_address = None;
+
+    while let Some(arg) = args.next() {
+        if arg != "--bind" {
+            return Err(format!("Unknown argument: {arg}"));
+        }
+
+        let value = args.next().ok_or("Missing address for --bind")?;
+        let address = value
+            .parse()
+            .map_err(|_| format!("Malformed address: {value}"))?;
+        bind_address = Some(address);
+    }
+
+    Ok(bind_address)
+}
+
+/// Handles a single Telnet client by echoing back every message it sent.
+struct TelnetStreamHandler {
+    telnet_session: TelnetSession,
+}
+
+impl TcpStreamHandler for TelnetStreamHandler {
+    fn on_connect(&mut self) -> Option<Vec<u8>> {
+        let initial_negotiation = self.telnet_session.initial_negotiation();
+
+        if !initial_negotiation.is_empty() {
+            Some(initial_negotiation)
+        } else {
+            None
+        }
+    }
+
+    fn accept(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut response = vec![];
+
+        if let Some(telnet_data) = self.telnet_session.accept_data(data) {
+            response.extend_from_slice(telnet_data.as_slice());
+        }
+
+        /* Events aren't used here, but they're queued until taken */
+        self.telnet_session.take_events();
+
+        if let Some(message_response) = generate_message_response(&mut self.telnet_session) {
+            let output = self
+                .telnet_session
+                .prepare_output(message_response.as_slice());
+            response.extend_from_slice(output.as_slice());
+        }
+
+        if !response.is_empty() {
+            Some(response)
+        } else {
+            None
+        }
     }
 
-    Ok(())
+    fn prepare_output(&mut self, data: &[u8]) -> Vec<u8> {
+        self.telnet_session.prepare_output(data)
+    }
+
+    fn on_idle(&mut self) -> Option<Vec<u8>> {
+        Some(self.telnet_session.keepalive())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.telnet_session.protocol_error().is_some()
+    }
 }
 
 fn generate_message_response(telnet_session: &mut TelnetSession) -> Option<Vec<u8>> {
@@ -74,3 +132,33 @@ fn generate_message_response(telnet_session: &mut TelnetSession) -> Option<Vec<u
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<SocketAddr>, String> {
+        parse_bind_address(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parse_bind_address_should_work() {
+        assert_eq!(parse(&[]), Ok(None));
+        assert_eq!(
+            parse(&["--bind", "0.0.0.0:2323"]),
+            Ok(Some("0.0.0.0:2323".parse().unwrap()))
+        );
+        assert_eq!(
+            parse(&["--bind", "[::1]:23"]),
+            Ok(Some("[::1]:23".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn parse_bind_address_should_reject_malformed_input() {
+        assert!(parse(&["--bind", "0.0.0.0"]).is_err());
+        assert!(parse(&["--bind", "localhost:99999"]).is_err());
+        assert!(parse(&["--bind"]).is_err());
+        assert!(parse(&["--port", "23"]).is_err());
+    }
+}
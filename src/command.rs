@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+use crate::telnet::TelnetEvent;
+
+/// Handler of a registered command. Receives the arguments of the command (every
+/// whitespace-delimited token after the command name) and returns the response.
+pub type CommandHandler = Box<dyn Fn(&[&str]) -> Vec<u8> + Send + Sync>;
+
+const COMMAND_HELP: &str = "help";
+
+/// Dispatches completed lines to registered command handlers, based on the first
+/// whitespace-delimited token of the line. A `help` command listing all registered
+/// commands is available by default.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::command::CommandRouter;
+///
+/// let mut router = CommandRouter::new();
+/// router.register("echo", |args| format!("{}\r\n", args.join(" ")).into_bytes());
+///
+/// assert_eq!(router.dispatch("echo hi"), Some(b"hi\r\n".to_vec()));
+/// ```
+pub struct CommandRouter {
+    /// Registered handlers, ordered by command name
+    handlers: BTreeMap<String, CommandHandler>,
+}
+
+impl CommandRouter {
+    /// Creates a new `CommandRouter` without any registered commands
+    pub fn new() -> CommandRouter {
+        CommandRouter {
+            handlers: BTreeMap::new(),
+        }
+    }
+
+    /// Registers `handler` for the command `name`. An already registered handler for
+    /// the same name (including `help`) is replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the command, i.e. the first token of a line
+    /// * `handler` - Handler that builds the response from the command arguments
+    pub fn register<F>(&mut self, name: &str, handler: F)
+    where
+        F: Fn(&[&str]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.handlers.insert(name.to_string(), Box::new(handler));
+    }
+
+    /// Dispatches a completed `line` to the handler of its command
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The completed line, without its line break
+    ///
+    /// # Returns
+    ///
+    /// The response of the handler, an error message for unknown commands or `None`
+    /// if the line is empty.
+    pub fn dispatch(&self, line: &str) -> Option<Vec<u8>> {
+        let mut tokens = line.split_whitespace();
+        let name = tokens.next()?;
+        let args = tokens.collect::<Vec<&str>>();
+
+        if let Some(handler) = self.handlers.get(name) {
+            return Some(handler(&args));
+        }
+
+        if name == COMMAND_HELP {
+            return Some(self.help());
+        }
+
+        Some(format!("Unknown command: {name}\r\n").into_bytes())
+    }
+
+    /// Dispatches given `event` if it's a completed line, see `dispatch`
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - An event of a `TelnetSession`
+    pub fn handle_event(&self, event: &TelnetEvent) -> Option<Vec<u8>> {
+        match event {
+            TelnetEvent::Line(line) => self.dispatch(line),
+            _ => None,
+        }
+    }
+
+    /// Returns the response of the default `help` command
+    fn help(&self) -> Vec<u8> {
+        let mut names = self
+            .handlers
+            .keys()
+            .map(|n| n.as_str())
+            .collect::<Vec<&str>>();
+        names.push(COMMAND_HELP);
+        names.sort_unstable();
+
+        format!("Available commands: {}\r\n", names.join(", ")).into_bytes()
+    }
+}
+
+impl Default for CommandRouter {
+    fn default() -> Self {
+        CommandRouter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telnet::TelnetSession;
+
+    fn create_router() -> CommandRouter {
+        let mut router = CommandRouter::new();
+        router.register("echo", |args| {
+            format!("{}\r\n", args.join(" ")).into_bytes()
+        });
+        router
+    }
+
+    #[test]
+    fn router_should_dispatch_lines() {
+        let router = create_router();
+        let mut session = TelnetSession::create(false);
+        session.accept_data(b"echo hi\r\n");
+
+        let responses = session
+            .take_events()
+            .iter()
+            .filter_map(|e| router.handle_event(e))
+            .collect::<Vec<Vec<u8>>>();
+        assert_eq!(responses, [b"hi\r\n"]);
+    }
+
+    #[test]
+    fn router_should_list_commands() {
+        let router = create_router();
+        assert_eq!(
+            router.dispatch("help"),
+            Some(b"Available commands: echo, help\r\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn router_should_handle_unknown_commands() {
+        let router = create_router();
+        assert_eq!(
+            router.dispatch("  foo bar"),
+            Some(b"Unknown command: foo\r\n".to_vec())
+        );
+        assert_eq!(router.dispatch("   "), None);
+    }
+}
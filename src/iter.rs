@@ -43,6 +43,55 @@ pub fn contains_sequence<T: Eq>(haystack: &[T], needle: &[T]) -> bool {
     false
 }
 
+/// Returns whether the byte sequence `needle` is a part of `haystack`, like
+/// `contains_sequence`. It uses the Boyer-Moore-Horspool algorithm, which skips parts
+/// of `haystack` that can't contain `needle`, so it's faster on large buffers.
+///
+/// # Arguments
+///
+/// * `haystack` - The bytes to look for `needle`
+/// * `needle` - The bytes that may be a part of `haystack`
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::iter::contains_sequence_fast;
+///
+/// assert!(contains_sequence_fast(b"some pasted text\x1b[201~", b"\x1b[201~"));
+/// assert!(!contains_sequence_fast(b"some pasted text", b"\x1b[201~"));
+/// ```
+pub fn contains_sequence_fast(haystack: &[u8], needle: &[u8]) -> bool {
+    if haystack.is_empty() {
+        /* Same edge case as contains_sequence */
+        return false;
+    }
+
+    if needle.len() > haystack.len() {
+        return false;
+    }
+
+    let Some(last) = needle.len().checked_sub(1) else {
+        return true;
+    };
+
+    /* Distance the window may be moved based on its last byte */
+    let mut shifts = [needle.len(); 256];
+    for (index, &byte) in needle[..last].iter().enumerate() {
+        shifts[usize::from(byte)] = last - index;
+    }
+
+    let mut start = 0;
+    while let Some(window) = haystack.get(start..start + needle.len()) {
+        if window == needle {
+            return true;
+        }
+
+        start += shifts[usize::from(window[last])];
+    }
+
+    false
+}
+
 /// Dequeues item from given `vec`, meaning returning and removing its first item.
 ///
 /// # Arguments
@@ -106,6 +155,54 @@ mod tests {
         assert!(!contains_sequence::<u8>(&[], &[]));
     }
 
+    #[test]
+    fn fast_finds_matches() {
+        assert!(contains_sequence_fast(&[1, 2, 3, 4, 5], &[1, 2, 3]));
+        assert!(contains_sequence_fast(&[1, 2, 3, 4, 5], &[2, 3, 4]));
+        assert!(contains_sequence_fast(&[1, 2, 3, 4, 5], &[3, 4, 5]));
+        assert!(contains_sequence_fast(&[1, 2, 3, 4, 5], &[]));
+    }
+
+    #[test]
+    fn fast_finds_no_match() {
+        assert!(!contains_sequence_fast(&[1, 2, 3, 4, 5], &[3, 3, 3]));
+        assert!(!contains_sequence_fast(&[], &[1]));
+        assert!(!contains_sequence_fast(&[], &[]));
+    }
+
+    #[test]
+    fn fast_finds_match_in_large_buffer() {
+        let mut haystack = vec![b'a'; 1024 * 1024];
+        assert!(!contains_sequence_fast(&haystack, b"\x1b[201~"));
+
+        haystack.extend_from_slice(b"\x1b[201~");
+        assert!(contains_sequence_fast(&haystack, b"\x1b[201~"));
+    }
+
+    #[test]
+    fn fast_matches_naive() {
+        /* Deterministic xorshift, so failures are reproducible */
+        let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for _ in 0..10_000 {
+            /* Small alphabet, so matches are likely */
+            let haystack: Vec<u8> = (0..random() % 32).map(|_| (random() % 3) as u8).collect();
+            let needle: Vec<u8> = (0..random() % 5).map(|_| (random() % 3) as u8).collect();
+
+            assert_eq!(
+                contains_sequence_fast(&haystack, &needle),
+                contains_sequence(&haystack, &needle),
+                "{haystack:?} {needle:?}"
+            );
+        }
+    }
+
     #[test]
     fn dequeue_works() {
         let mut vec = vec![1, 2];
@@ -1,2 +1,6 @@
+pub mod command;
 pub mod iter;
+pub mod tcp;
 pub mod telnet;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
@@ -1,26 +1,47 @@
 use log::error;
+use std::collections::{HashMap, HashSet};
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
 
-use crate::iter::{contains_sequence, dequeue};
-
-const CHAR_ECHO: u8 = 1;
-const CHAR_BEL: u8 = 7;
-const CHAR_BACK_SPACE: u8 = 8;
-const CHAR_ESCAPE: u8 = 27;
-const CHAR_DELETE: u8 = 127;
-const CHAR_SUB_NEGOTIATION_END: u8 = 240;
-const CHAR_ERASE_CHARACTER: u8 = 247;
-const CHAR_ERASE_LINE: u8 = 248;
-const CHAR_SUB_NEGOTIATION: u8 = 250;
-const CHAR_WILL: u8 = 251;
-const CHAR_WONT: u8 = 252;
-const CHAR_DO: u8 = 253;
-const CHAR_DONT: u8 = 254;
-const CHAR_IAC: u8 = 255;
+#[cfg(feature = "mccp")]
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::iter::dequeue;
+
+pub mod constants;
+pub mod framed;
+pub mod parser;
+#[cfg(feature = "ansi")]
+pub mod text;
+
+use constants::*;
+use parser::{TelnetParser, TelnetToken};
+
+/// Prefix of the terminal type that carries the MTTS bitmask
+const MTTS_PREFIX: &str = "MTTS ";
+
+/// Maximum number of terminal types that are requested during a TTYPE cycle
+const MAX_TERMINAL_TYPES: usize = 8;
+
+/// Maximum number of NEW-ENVIRON variables that are stored, further ones are dropped
+const MAX_ENVIRONMENT_VARIABLES: usize = 64;
+
+/// Size of the chunks that `run_blocking` reads at once
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Time the client has to answer a request to enable an option, see
+/// `set_negotiation_timeout`
+const DEFAULT_NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of first incoming bytes that are inspected to detect clients that don't
+/// speak Telnet, see `TelnetEvent::SuspectedNonTelnetClient`
+const NON_TELNET_DETECTION_BYTES: usize = 64;
 
 const CHARS_LINE_BREAK: [char; 2] = ['\r', '\n'];
 
 /// May identify the end of an ANSI escape sequence
-const CHARS_ESCAPE_SEQUENCE_END: [char; 20] = [
+#[cfg(feature = "ansi")]
+const CHARS_ESCAPE_SEQUENCE_END: [char; 21] = [
     'A', /* CUU */
     'B', /* CUD */
     'C', /* CUF */
@@ -41,40 +62,701 @@ const CHARS_ESCAPE_SEQUENCE_END: [char; 20] = [
     'u', /* RCP, SCORC */
     'h', /* DECTCEM */
     'l', /* DECTCEM */
+    '~', /* Keys (e.g. Delete) and bracketed paste */
 ];
 
+/// Starts a bracketed paste (`ESC [ 200 ~`)
+#[cfg(feature = "ansi")]
+const BRACKETED_PASTE_START: &[u8] = b"\x1b[200~";
+/// Ends a bracketed paste (`ESC [ 201 ~`)
+#[cfg(feature = "ansi")]
+const BRACKETED_PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Flashes the screen by briefly enabling reverse video (DECSCNM, `ESC [ ? 5 h` and
+/// `ESC [ ? 5 l`)
+const VISUAL_BELL: &[u8] = b"\x1b[?5h\x1b[?5l";
+
 /// Telnet session "state machine", represents the current state
 /// of a Telnet session.
 pub struct TelnetSession {
     /// Buffer for read non-command data (aka. incoming message)
     data: Vec<char>,
+    /// Raw bytes of `data`, exactly as they arrived
+    data_bytes: Vec<u8>,
     /// Stream of incoming, not interpreted data
     stream: Vec<u8>,
-    /// Current state of the session
+    /// Responses that weren't taken yet, see `receive`
+    pending_output: Vec<(OutputKind, Vec<u8>)>,
+    /// Returns whether the last data byte was a `\r` that already completed a line, so
+    /// a directly following `\n` doesn't complete another one
+    is_after_carriage_return: bool,
+    /// Splits incoming data into data bytes and commands
+    parser: TelnetParser,
+    /// Current state of the incoming data
     state: TelnetState,
+    /// Returns whether incoming data is buffered without processing it, see
+    /// `pause_input`
+    is_paused: bool,
     /// Returns whether every incoming, non-command char should be echoed back to the client
     is_echoing: bool,
-    /// Buffer for currently read ANSI escape sequence, if they should be passed on
-    ansi_escape_sequence_buffer: Option<Vec<char>>,
+    /// Char that is echoed instead of printable incoming chars (e.g. for password prompts)
+    echo_mask: Option<char>,
+    /// Returns whether control chars are echoed in caret notation, see `set_caret_echo`
+    is_caret_echo: bool,
+    /// Returns whether lines are edited server-side, see `set_server_side_line_editing`
+    is_editing_lines: bool,
+    /// Number of bytes of the current line right of the cursor, while lines are edited
+    /// server-side
+    edit_cursor_offset: usize,
+    /// Returns whether `set_server_side_line_editing` turned the echo on, so disabling
+    /// it turns the echo off again
+    is_echo_by_line_editing: bool,
+    /// Maximum length of a single line in the data buffer, see `set_max_line_length`
+    max_line_length: Option<usize>,
+    /// Width of the tab stops that incoming tabs are expanded to, see `set_tab_stops`
+    tab_stops: Option<usize>,
+    /// Handling of incoming NUL bytes, see `set_nul_handling`
+    nul_handling: NulHandling,
+    /// Returns whether lone `\n` of outgoing data are converted to CRLF, see
+    /// `set_output_crlf`
+    is_converting_output_line_breaks: bool,
+    /// Returns whether the last outgoing data byte was a `\r`, so a directly following
+    /// `\n` of the next output isn't lone
+    is_output_after_carriage_return: bool,
+    /// Bytes of an incomplete UTF-8 char that are held back from echoing. Commands
+    /// between these bytes (e.g. `IAC NOP`) don't affect it, so the char is still
+    /// completed by the following data bytes.
+    echo_utf8_buffer: Vec<u8>,
+    /// Returns whether records (e.g. prompts) should be terminated by `IAC EOR` instead of `IAC GA`
+    is_end_of_record_active: bool,
+    /// Returns whether the session agreed to suppress `IAC GA` (`IAC DO SUPPRESS-GO-AHEAD`)
+    is_suppressing_go_ahead: bool,
+    /// Upper limits of the buffers, see `BufferLimits`
+    buffer_limits: BufferLimits,
+    /// Flags of the LINEMODE MODE that the client reported
+    linemode_flags: u8,
+    /// Returns whether the environment of the client was requested (`IAC DO NEW-ENVIRON`)
+    is_environment_requested: bool,
+    /// Environment variables that the client reported via NEW-ENVIRON
+    environment: HashMap<String, String>,
+    /// Encoding that incoming text is decoded with
+    encoding: Encoding,
+    /// Returns whether the encoding is set based on the locale of the client's environment
+    is_charset_detection_enabled: bool,
+    /// Returns whether the terminal type of the client was requested (`IAC DO TTYPE`)
+    is_terminal_type_requested: bool,
+    /// Terminal types the client reported during the TTYPE cycle
+    terminal_types: Vec<String>,
+    /// Capabilities the client reported via MTTS at the end of the TTYPE cycle
+    terminal_capabilities: Option<TerminalCapabilities>,
+    /// Returns whether the session sends its own terminal type, as requested by the
+    /// client (`IAC DO TTYPE`)
+    is_sending_terminal_type: bool,
+    /// Terminal type that's sent to clients asking for it, see
+    /// `set_server_terminal_type`
+    server_terminal_type: String,
+    /// Returns whether the X display location of the client was requested
+    /// (`IAC DO XDISPLOC`)
+    is_x_display_location_requested: bool,
+    /// X display location the client reported via XDISPLOC, e.g. `host:0.0`
+    x_display_location: Option<String>,
+    /// Location the client reported via SEND-LOCATION, e.g. `Building 1, Room 2`
+    client_location: Option<String>,
+    /// Returns whether the terminal speed of the client was requested (`IAC DO TSPEED`)
+    is_terminal_speed_requested: bool,
+    /// Terminal speed (transmit, receive) the client reported via TSPEED
+    terminal_speed: Option<(u32, u32)>,
+    /// Window size (width, height) the client reported via NAWS
+    window_size: Option<(u16, u16)>,
+    /// Returns whether flow control is on, as reported via LFLOW. It only applies
+    /// while LFLOW is enabled.
+    is_flow_control_on: bool,
+    /// Way output that was stopped by XOFF is restarted, as reported via LFLOW
+    flow_control_restart: Option<FlowControlRestart>,
+    /// Returns whether the client stopped the output via XOFF, see `is_output_paused`
+    is_output_paused: bool,
+    /// Decides about the authentication data of the client, see `set_auth_backend`
+    auth_backend: Option<Box<dyn AuthBackend + Send + Sync>>,
+    /// Returns whether the client was asked to authenticate (`IAC DO AUTHENTICATION`)
+    is_authentication_requested: bool,
+    /// Returns whether the auth backend accepted the authentication data of the client
+    is_authenticated: bool,
+    /// Returns whether ANSI escape sequences should be passed on
+    #[cfg(feature = "ansi")]
+    is_passing_escape_sequences: bool,
+    /// Buffer for currently read ANSI escape sequence
+    #[cfg(feature = "ansi")]
+    escape_sequence: Vec<u8>,
+    /// Returns whether unknown escape sequences are answered by BEL, see
+    /// `set_bell_on_unknown_escape`
+    #[cfg(feature = "ansi")]
+    is_bell_on_unknown_escape: bool,
+    /// Buffer for currently pasted text, while in a bracketed paste
+    #[cfg(feature = "ansi")]
+    paste: Option<Vec<u8>>,
+    /// Point in time of the last activity (incoming data) of the client
+    last_activity: Instant,
+    /// Events that occurred while accepting data and weren't taken yet
+    events: Vec<TimedEvent>,
+    /// Returns whether events are timestamped when they occur, see
+    /// `set_event_timestamps`
+    is_timestamping_events: bool,
+    /// Handling of protocol errors of the client, see `set_error_policy`
+    error_policy: ErrorPolicy,
+    /// Protocol error that ended the session, see `protocol_error`
+    fatal_error: Option<ProtocolError>,
+    /// First incoming bytes, as long as it's undecided whether the client speaks
+    /// Telnet, see `TelnetEvent::SuspectedNonTelnetClient`
+    first_bytes: Option<Vec<u8>>,
+    /// Variables that are reported to MSSP (MUD Server Status Protocol) crawlers
+    mssp_variables: Vec<(String, String)>,
+    /// Current state of the MCCP2 output compression
+    #[cfg(feature = "mccp")]
+    compression: CompressionState,
+    /// Decides whether options the client asks for are accepted, see `set_option_policy`
+    option_policy: Option<Box<OptionPolicy>>,
+    /// Options the client enabled on its side (it sent `WILL` and the session agreed)
+    remote_options: HashSet<u8>,
+    /// Handles sub negotiations of unknown options, see `set_subneg_fallback`
+    sub_negotiation_fallback: Option<Box<SubNegotiationFallback>>,
+    /// Handler of commands the session doesn't implement itself
+    unknown_command_handler: Option<Box<UnknownCommandHandler>>,
+    /// Filters that are applied to every completed line, in registration order
+    input_filters: Vec<Box<InputFilter>>,
+    /// Options the client was asked to enable (`IAC DO option`) that it didn't answer
+    /// yet, with the point in time of the request
+    requested_options: HashMap<u8, Instant>,
+    /// Time the client has to answer a request to enable an option, see `tick`
+    negotiation_timeout: Duration,
+    /// Output that's sent on connect, see `initial_negotiation`
+    initial_output: Vec<u8>,
+    /// Observer that is notified synchronously while accepting data, see `with_observer`
+    observer: Option<Box<dyn TelnetObserver + Send + Sync>>,
+    /// Recorder of all incoming and outgoing bytes, see `set_trace`
+    trace: Option<Trace>,
+}
+
+/// Recorder of the bytes a `TelnetSession` receives and sends, see
+/// `TelnetSession::set_trace`
+struct Trace {
+    /// Receives the readable trace
+    writer: Box<dyn Write + Send + Sync>,
+    /// Point in time the trace started, timestamps are relative to it
+    start: Instant,
+}
+
+/// Observer of a `TelnetSession`, as an alternative to polling `take_events`. All
+/// callbacks are invoked synchronously during `accept_data` and do nothing by default.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::{TelnetObserver, TelnetSession};
+///
+/// struct LineLogger;
+///
+/// impl TelnetObserver for LineLogger {
+///     fn on_line(&mut self, line: &str) {
+///         println!("Received {line}");
+///     }
+/// }
+///
+/// let mut telnet_session = TelnetSession::create(false).with_observer(LineLogger);
+/// telnet_session.accept_data(b"hello\r\n"); // prints "Received hello"
+/// ```
+pub trait TelnetObserver {
+    /// Called when the client completed a line, without its line break
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The completed line
+    fn on_line(&mut self, line: &str) {
+        let _ = line;
+    }
+
+    /// Called when the client negotiated an option
+    ///
+    /// # Arguments
+    ///
+    /// * `option` - The negotiated option
+    /// * `enabled` - Whether the client wants the option enabled (`WILL`, `DO`) or
+    ///   disabled (`WONT`, `DONT`)
+    fn on_option(&mut self, option: u8, enabled: bool) {
+        let _ = (option, enabled);
+    }
+
+    /// Called when the client completed a sub negotiation
+    ///
+    /// # Arguments
+    ///
+    /// * `option` - The negotiated option
+    /// * `data` - The unescaped sub negotiation parameters
+    fn on_subneg(&mut self, option: u8, data: &[u8]) {
+        let _ = (option, data);
+    }
+}
+
+/// Backend that decides about the authentication of clients via AUTHENTICATION
+/// (option 37). The session handles the negotiation, the backend the actual
+/// authentication, see `TelnetSession::set_auth_backend`.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::{AuthBackend, AuthDecision};
+///
+/// struct SharedSecret;
+///
+/// impl AuthBackend for SharedSecret {
+///     fn auth_types(&self) -> Vec<(u8, u8)> {
+///         vec![(99, 0)]
+///     }
+///
+///     fn authenticate(&mut self, _auth_type: u8, _modifier: u8, data: &[u8]) -> AuthDecision {
+///         match data {
+///             b"secret" => AuthDecision::Accept(b"welcome".to_vec()),
+///             _ => AuthDecision::Reject(vec![]),
+///         }
+///     }
+/// }
+/// ```
+pub trait AuthBackend {
+    /// Returns the supported authentication type pairs (type, modifier), in order of
+    /// preference. They're sent to the client in `IAC SB AUTHENTICATION SEND`.
+    fn auth_types(&self) -> Vec<(u8, u8)>;
+
+    /// Decides about the authentication data the client sent via
+    /// `IAC SB AUTHENTICATION IS type modifier data IAC SE`
+    ///
+    /// # Arguments
+    ///
+    /// * `auth_type` - The authentication type the client chose
+    /// * `modifier` - The modifier of the authentication type
+    /// * `data` - The unescaped authentication data
+    ///
+    /// # Returns
+    ///
+    /// Whether the client is authenticated, including the data that's sent back via
+    /// `IAC SB AUTHENTICATION REPLY type modifier data IAC SE`.
+    fn authenticate(&mut self, auth_type: u8, modifier: u8, data: &[u8]) -> AuthDecision;
+}
+
+/// Decision of an `AuthBackend` about the authentication data of a client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthDecision {
+    /// The client is authenticated, the data is sent back in the reply
+    Accept(Vec<u8>),
+    /// The client is not authenticated, the data is sent back in the reply
+    Reject(Vec<u8>),
+}
+
+/// Callback that decides whether an option negotiation is accepted
+type OptionPolicy = dyn Fn(u8, Negotiation) -> bool + Send + Sync;
+
+/// Callback that handles sub negotiations of options the session doesn't implement
+type SubNegotiationFallback = dyn FnMut(u8, &[u8]) -> Option<Vec<u8>> + Send + Sync;
+
+/// Callback that handles commands the session doesn't implement
+type UnknownCommandHandler = dyn FnMut(u8) -> Option<Vec<u8>> + Send + Sync;
+
+/// Callback that transforms a completed line
+type InputFilter = dyn FnMut(&mut Vec<u8>) + Send + Sync;
+
+/// Enumeration of option negotiation commands (RFC-854)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Negotiation {
+    /// The sender wants to enable an option on its side
+    Will,
+    /// The sender refuses to enable or disables an option on its side
+    Wont,
+    /// The sender asks the receiver to enable an option
+    Do,
+    /// The sender asks the receiver to disable an option
+    Dont,
+}
+
+/// Enumeration of events that may occur while a `TelnetSession` accepts data. Some
+/// events only exist with certain features (e.g. `ControlSequence` with `ansi`), so
+/// matches always need a wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TelnetEvent {
+    /// The client sent an option negotiation, e.g. `IAC WILL ECHO`
+    Negotiation {
+        /// The negotiation command (WILL, WONT, DO, DONT)
+        command: u8,
+        /// The negotiated option
+        option: u8,
+    },
+    /// The client didn't answer the request to enable an option in time, see
+    /// `TelnetSession::tick`. The option is treated as refused.
+    NegotiationTimeout {
+        /// The requested option
+        option: u8,
+    },
+    /// The client sent a Synch (`IAC DM`), all data before the mark was discarded
+    Synch,
+    /// The client sent a BREAK (`IAC BRK`), the partial input line was discarded
+    Break,
+    /// The client completed a line (without its line break). The line is still
+    /// part of the data buffer.
+    Line(String),
+    /// The client made a protocol error, unless the error policy ignores them (see
+    /// `TelnetSession::set_error_policy`)
+    ProtocolError(ProtocolError),
+    /// The first incoming bytes (64) contain no negotiation, but mostly unprintable
+    /// data. The client probably doesn't speak Telnet (e.g. a port scanner), so the
+    /// server may disconnect it. Occurs at most once.
+    SuspectedNonTelnetClient,
+    /// The client completed a length-prefixed frame (without its prefix), see
+    /// `framed::FramedSession`
+    Frame(Vec<u8>),
+    /// The client pasted text via bracketed paste (`ESC [ 200 ~` ... `ESC [ 201 ~`). The
+    /// text is not interpreted (e.g. it may contain line breaks) and not part of the
+    /// data buffer.
+    Paste(String),
+    /// The client sent an ANSI escape sequence (e.g. a cursor key), regardless of
+    /// whether it's passed on. Only sent with the `ansi` feature.
+    #[cfg(feature = "ansi")]
+    ControlSequence {
+        /// The complete escape sequence, e.g. `ESC [ 1 ; 31 m`
+        sequence: Vec<u8>,
+        /// The decoded attributes, if it's an SGR escape sequence (`ESC [ ... m`)
+        sgr: Vec<text::SgrAttribute>,
+    },
+}
+
+/// Enumeration of protocol errors of the client that the session can recover from,
+/// see `TelnetSession::set_error_policy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProtocolError {
+    /// A negotiation (e.g. `IAC WILL`) was followed by an `IAC` instead of an option
+    IncompleteNegotiation,
+    /// A sub negotiation exceeded its buffer limit, see `BufferLimits`
+    OversizedSubNegotiation {
+        /// The negotiated option, if any
+        option: Option<u8>,
+    },
+    /// A sub negotiation was interrupted by a command other than `IAC SE`
+    AbortedSubNegotiation {
+        /// The negotiated option, if any
+        option: Option<u8>,
+    },
+    /// The parameters of a sub negotiation are malformed, e.g. a NAWS sub negotiation
+    /// of the wrong length
+    MalformedSubNegotiation {
+        /// The negotiated option
+        option: u8,
+    },
+}
+
+/// Enumeration of ways to handle protocol errors of the client, see
+/// `TelnetSession::set_error_policy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// The error is logged and the session carries on as well as possible, e.g. a
+    /// command that interrupts a sub negotiation is still handled
+    #[default]
+    Ignore,
+    /// The erroneous sequence is discarded and `TelnetEvent::ProtocolError` occurs. A
+    /// command that interrupts a sub negotiation is still handled, so its bytes aren't
+    /// mistaken for data.
+    Recover,
+    /// `TelnetEvent::ProtocolError` occurs and the error is fatal: All further input is
+    /// discarded and the connection should be closed, see `TelnetSession::protocol_error`
+    Disconnect,
+}
+
+/// A `TelnetEvent` with the point in time it occurred, see
+/// `TelnetSession::take_timed_events`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedEvent {
+    /// The event
+    pub event: TelnetEvent,
+    /// Point in time the event occurred, if timestamps were enabled at that time (see
+    /// `TelnetSession::set_event_timestamps`)
+    pub timestamp: Option<Instant>,
 }
 
-/// Enumeration of states that the `TelnetSession` may have on the server side.
+/// Segment of Telnet data, see `split_telnet`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TelnetSegment {
+    /// Data between commands, escaped `IAC IAC` are unescaped to a single `IAC`
+    Text(Vec<u8>),
+    /// A complete command sequence as it was received, e.g. `IAC WILL ECHO` or a
+    /// whole sub negotiation up to `IAC SE`
+    Command(Vec<u8>),
+    /// An incomplete command sequence at the end of the data. It's completed by the
+    /// data that follows it.
+    Partial(Vec<u8>),
+}
+
+/// Enumeration of ways to handle incoming NUL bytes, see `TelnetSession::set_nul_handling`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NulHandling {
+    /// NUL bytes are stripped, so `CR NUL` (a bare carriage return) becomes a single `\r`
+    #[default]
+    Strip,
+    /// Like `Strip`, but `CR NUL` becomes a line break (`\r\n`)
+    LineFeed,
+    /// NUL bytes are passed on like any other data
+    Keep,
+}
+
+/// Enumeration of the ways output that was stopped by XOFF is restarted, as reported
+/// via LFLOW (option 33)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControlRestart {
+    /// Any char restarts the output (`RESTART-ANY`)
+    Any,
+    /// Only XON restarts the output (`RESTART-XON`)
+    Xon,
+}
+
+/// Enumeration of encodings that incoming text (e.g. lines) is decoded with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// ISO-8859-1, every byte is a single char. It's a superset of ASCII, the charset of
+    /// the network virtual terminal.
+    #[default]
+    Latin1,
+    /// UTF-8, invalid sequences are replaced by U+FFFD
+    Utf8,
+}
+
+impl Encoding {
+    /// Returns the encoding of given charset name (e.g. `UTF-8`), if it's supported
+    ///
+    /// # Arguments
+    ///
+    /// * `charset` - The name of the charset, case insensitive
+    pub fn from_charset(charset: &str) -> Option<Encoding> {
+        match charset.to_ascii_uppercase().replace('_', "-").as_str() {
+            "UTF-8" | "UTF8" => Some(Encoding::Utf8),
+            "ISO-8859-1" | "ISO8859-1" | "LATIN1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    /// Decodes given bytes
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The encoded text
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        }
+    }
+}
+
+/// Terminal capabilities a client reported via MTTS (Mud Terminal Type Standard)
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::TerminalCapabilities;
+///
+/// let capabilities = TerminalCapabilities::from_bits(5);
+/// assert!(capabilities.contains(TerminalCapabilities::ANSI | TerminalCapabilities::UTF_8));
+/// assert!(!capabilities.contains(TerminalCapabilities::VT100));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct TerminalCapabilities(u32);
+
+impl TerminalCapabilities {
+    /// Client supports all common ANSI color codes
+    pub const ANSI: TerminalCapabilities = TerminalCapabilities(1);
+    /// Client supports all common VT100 codes
+    pub const VT100: TerminalCapabilities = TerminalCapabilities(2);
+    /// Client is using UTF-8 character encoding
+    pub const UTF_8: TerminalCapabilities = TerminalCapabilities(4);
+    /// Client supports all 256 color codes
+    pub const COLORS_256: TerminalCapabilities = TerminalCapabilities(8);
+    /// Client supports xterm mouse tracking
+    pub const MOUSE_TRACKING: TerminalCapabilities = TerminalCapabilities(16);
+    /// Client supports the OSC color palette
+    pub const OSC_COLOR_PALETTE: TerminalCapabilities = TerminalCapabilities(32);
+    /// Client is using a screen reader
+    pub const SCREEN_READER: TerminalCapabilities = TerminalCapabilities(64);
+    /// Client is a proxy allowing different users to connect from the same IP address
+    pub const PROXY: TerminalCapabilities = TerminalCapabilities(128);
+    /// Client supports truecolor codes
+    pub const TRUECOLOR: TerminalCapabilities = TerminalCapabilities(256);
+    /// Client supports the Mud New Environment Standard
+    pub const MNES: TerminalCapabilities = TerminalCapabilities(512);
+    /// Client supports the Mud Server Link Protocol
+    pub const MSLP: TerminalCapabilities = TerminalCapabilities(1024);
+    /// Client supports SSL for data encryption
+    pub const SSL: TerminalCapabilities = TerminalCapabilities(2048);
+
+    /// Creates `TerminalCapabilities` from an MTTS bitmask
+    ///
+    /// # Arguments
+    ///
+    /// * `bits` - The MTTS bitmask
+    pub fn from_bits(bits: u32) -> TerminalCapabilities {
+        TerminalCapabilities(bits)
+    }
+
+    /// Returns the MTTS bitmask
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns whether all capabilities of `other` are included
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The capabilities to check
+    pub fn contains(&self, other: TerminalCapabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for TerminalCapabilities {
+    type Output = TerminalCapabilities;
+
+    fn bitor(self, rhs: TerminalCapabilities) -> TerminalCapabilities {
+        TerminalCapabilities(self.0 | rhs.0)
+    }
+}
+
+/// Enumeration of states of the MCCP2 (option 86) output compression.
+#[cfg(feature = "mccp")]
+enum CompressionState {
+    /// Compression was neither offered nor requested
+    Disabled,
+    /// `IAC WILL MCCP2` was sent, waiting for the client to agree
+    Offered,
+    /// Client agreed, compression starts after the currently sent marker
+    Starting,
+    /// Every outgoing byte is compressed
+    Active(ZlibEncoder<Vec<u8>>),
+    /// Client refused, compression ends before the currently sent `IAC WONT MCCP2`
+    Ending(ZlibEncoder<Vec<u8>>),
+}
+
+/// Kinds of queued responses, see `TelnetSession::receive`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputKind {
+    /// Responses to commands, e.g. negotiation replies
+    Command,
+    /// Responses to data, e.g. echo
+    Data,
+    /// The `IAC SB MCCP2 IAC SE` marker, everything after it is compressed
+    #[cfg(feature = "mccp")]
+    CompressionStart,
+    /// The `IAC WONT MCCP2` reply, the compressed stream is finished before it
+    #[cfg(feature = "mccp")]
+    CompressionEnd,
+}
+
+/// Enumeration of states of the incoming data of a `TelnetSession`. Commands are split
+/// off by its `TelnetParser` before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum TelnetState {
     /// Incoming, non-command data (e.g. text)
     Idle,
-    /// Incoming command data (e.g. WILL, WONT, DO, DONT)
+    /// Incoming escape sequence
+    #[cfg(feature = "ansi")]
+    AnsiEscapeSequence,
+}
+
+/// Transition of a `TelnetSession` on an incoming data byte: The state it enters and the
+/// response that should be sent to the Telnet client, if any
+type Transition = (TelnetState, Option<Vec<u8>>);
+
+/// Updates a `TelnetSession` in a certain state based on the next incoming data byte
+type StateUpdate = fn(&mut TelnetSession, u8) -> Transition;
+
+impl TelnetState {
+    /// Returns the update of a session in this state. Together, these form the
+    /// transition table of the session: Every update returns the next state, which
+    /// decides about the update of the next byte.
+    fn update(self) -> StateUpdate {
+        match self {
+            TelnetState::Idle => update_session_idle,
+            #[cfg(feature = "ansi")]
+            TelnetState::AnsiEscapeSequence => update_session_escape_sequence,
+        }
+    }
+}
+
+/// Upper limits of the buffers of a `TelnetSession`, so a misbehaving client can't make
+/// it grow without bounds. Data exceeding a limit is discarded.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::{BufferLimits, TelnetSession};
+///
+/// let mut telnet_session = TelnetSession::create(false);
+/// telnet_session.set_buffer_limits(BufferLimits {
+///     data: 4,
+///     ..BufferLimits::default()
+/// });
+/// telnet_session.accept_data(b"abcdef");
+///
+/// assert_eq!(telnet_session.data_bytes(), b"abcd");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferLimits {
+    /// Maximum number of bytes in the data buffer, until it's cleared. Line breaks may
+    /// exceed it by two bytes, so the current line can still be completed.
+    pub data: usize,
+    /// Maximum number of payload bytes of a single sub negotiation. Longer sub
+    /// negotiations are dropped completely.
+    pub sub_negotiation: usize,
+    /// Maximum length of a single ANSI escape sequence that's passed on. Longer
+    /// sequences are dropped.
+    pub escape_sequence: usize,
+    /// Maximum number of queued events, until they're taken (e.g. via `take_events`).
+    /// Further events are dropped.
+    pub events: usize,
+}
+
+impl Default for BufferLimits {
+    fn default() -> Self {
+        BufferLimits {
+            data: 64 * 1024,
+            sub_negotiation: 8 * 1024,
+            escape_sequence: 64,
+            events: 1024,
+        }
+    }
+}
+
+/// Public, read-only view of the internal state of a `TelnetSession`, e.g. for logging
+/// sessions that are stuck in a sub negotiation. See `TelnetSession::state`.
+///
+/// Some states only exist with certain features (e.g. `AnsiEscapeSequence` with
+/// `ansi`), so matches always need a wildcard arm and enabling a feature doesn't break
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParserState {
+    /// Incoming, non-command data (e.g. text)
+    Idle,
+    /// Incoming command data, after an `IAC`
     Command,
-    /// Incoming command data for WILL command
+    /// Incoming option of a WILL command
     CommandWill,
-    /// Incoming command data for WONT command
+    /// Incoming option of a WONT command
     CommandWont,
-    /// Incoming command data for DO command
+    /// Incoming option of a DO command
     CommandDo,
-    /// Incoming command data for DONT command
+    /// Incoming option of a DONT command
     CommandDont,
-    /// Incoming command data for sub negotiation command
+    /// Incoming sub negotiation data
     SubNegotiation,
-    /// Incoming escape sequence
+    /// Incoming IAC within sub negotiation data (e.g. IAC SE)
+    SubNegotiationIac,
+    /// Incoming ANSI escape sequence
+    #[cfg(feature = "ansi")]
     AnsiEscapeSequence,
 }
 
@@ -82,6 +764,10 @@ impl TelnetSession {
     /// Accepts incoming tcp stream data and maybe returns a response that should be sent
     /// back to the client.
     ///
+    /// Commands may be split across multiple calls (e.g. a read ending in a lone `IAC`).
+    /// The session keeps its state between calls, so the command is completed by the
+    /// bytes of the next call.
+    ///
     /// # Arguments
     ///
     /// * `data` - Incoming TCP stream data
@@ -90,26 +776,8 @@ impl TelnetSession {
     ///
     /// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
     pub fn accept_data(&mut self, data: &[u8]) -> Option<Vec<u8>> {
-        /* Append incoming data */
-        self.stream.extend_from_slice(data);
-        let mut response: Vec<u8> = vec![];
-
-        while let Some(next) = dequeue(&mut self.stream) {
-            let result = match self.state {
-                TelnetState::Idle => update_session_idle(self, next),
-                TelnetState::Command => update_session_command(self, next),
-                TelnetState::CommandWill => update_session_will(self, next),
-                TelnetState::CommandWont => update_session_wont(self, next),
-                TelnetState::CommandDo => update_session_do(self, next),
-                TelnetState::CommandDont => update_session_dont(self, next),
-                TelnetState::SubNegotiation => update_session_sub_negotiation(self, next),
-                TelnetState::AnsiEscapeSequence => update_session_escape_sequence(self, next),
-            };
-
-            if let Some(v) = result {
-                response.extend_from_slice(v.as_slice());
-            }
-        }
+        self.receive(data);
+        let response = self.take_output();
 
         if !response.is_empty() {
             Some(response)
@@ -118,153 +786,3389 @@ impl TelnetSession {
         }
     }
 
-    /// Returns currently read (non-command) data from data stream
+    /// Accepts incoming tcp stream data like `accept_data`, but queues the response
+    /// instead of returning it. The queued response is split into command responses
+    /// (e.g. negotiation replies) and data responses (e.g. echo), which can be taken
+    /// separately via `take_commands` and `take_data`, or together via `take_output`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Incoming TCP stream data
     ///
     /// # Examples
     ///
     /// ```
     /// use telnet_server::telnet::TelnetSession;
     ///
-    /// let telnet_session = TelnetSession::create();
-    ///
-    /// // data (e.g. a user-name) is sent from client...
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.receive(&[255, 253, 1]); // IAC DO ECHO
+    /// telnet_session.receive(b"hi");
     ///
-    /// let data = telnet_session.get_data_buffer();
-    /// let message = data.iter().collect::<String>();
-    /// println!("{}", message); // "admin"
+    /// assert_eq!(telnet_session.take_data(), b"hi");
+    /// assert_eq!(telnet_session.take_commands(), [255, 251, 1]); // IAC WILL ECHO
     /// ```
-    pub fn get_data_buffer(&self) -> &Vec<char> {
-        &self.data
+    pub fn receive(&mut self, data: &[u8]) {
+        if !data.is_empty() {
+            self.mark_activity();
+        }
+
+        self.record_trace("<", data);
+
+        if let Some(first_bytes) = self.first_bytes.as_mut() {
+            let missing = NON_TELNET_DETECTION_BYTES.saturating_sub(first_bytes.len());
+            first_bytes.extend_from_slice(&data[..data.len().min(missing)]);
+        }
+
+        /* Append incoming data */
+        if self.is_paused {
+            let free = self.buffer_limits.data.saturating_sub(self.stream.len());
+            if data.len() > free {
+                error!("Input buffer full while paused, discarding data");
+            }
+
+            self.stream.extend_from_slice(&data[..data.len().min(free)]);
+            return;
+        }
+
+        self.stream.extend_from_slice(data);
+        self.process_stream();
     }
 
-    /// Clears currently read (non-command) data from buffer.
-    /// This can be useful if you already handled the data and don't need it anymore.
+    /// Stops processing incoming data, e.g. while the server is busy. Until
+    /// `resume_input` is called, incoming data is only buffered: Nothing is echoed and
+    /// no events occur. The buffer is limited to `BufferLimits::data` bytes, further
+    /// data is discarded.
     ///
     /// # Examples
     ///
     /// ```
     /// use telnet_server::telnet::TelnetSession;
     ///
-    /// let mut telnet_session = TelnetSession::create();
-    ///
-    /// // data (e.g. a user-name) is sent from client...
-    ///
-    /// let data = telnet_session.get_data_buffer();
-    /// let message = data.iter().collect::<String>();
-    /// println!("{}", message); // "admin"
-    ///
-    /// telnet_session.clear_data_buffer();
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.pause_input();
+    /// telnet_session.accept_data(b"hi\r\n");
+    /// assert!(telnet_session.take_events().is_empty());
     ///
-    /// let data = telnet_session.get_data_buffer();
-    /// assert!(data.is_empty());
+    /// telnet_session.resume_input();
+    /// assert_eq!(telnet_session.take_events().len(), 1);
     /// ```
-    pub fn clear_data_buffer(&mut self) {
-        self.data.clear()
+    pub fn pause_input(&mut self) {
+        self.is_paused = true;
     }
 
-    /// Creates a new `TelnetSettion`
-    ///
-    /// # Arguments
-    /// * `pass_ansi_escape_sequences` - if true, ANSI escape sequences will be
-    ///   handled, returned etc. Otherwise they will be ignored
-    pub fn create(pass_ansi_escape_sequences: bool) -> TelnetSession {
-        let ansi_escape_sequence_buffer = if pass_ansi_escape_sequences {
-            Some(vec![])
-        } else {
-            None
-        };
-
-        TelnetSession {
-            data: vec![],
-            stream: vec![],
-            state: TelnetState::Idle,
-            is_echoing: false,
-            ansi_escape_sequence_buffer,
-        }
+    /// Continues processing incoming data after `pause_input`, starting with the data that
+    /// was buffered in the meantime. Responses are queued like by `receive`, see
+    /// `take_output`.
+    pub fn resume_input(&mut self) {
+        self.is_paused = false;
+        self.process_stream();
     }
-}
 
-/// Updates given `session` in `TelnetState::Idle` based on `next` incoming byte
-///
-/// # Arguments
-///
-/// * `session` - The affected `TelnetSession`
-/// * `next` - The next incoming byte
-///
-/// # Returns
-///
-/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
-fn update_session_idle(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
-    match next {
-        CHAR_IAC => session.state = TelnetState::Command,
-        CHAR_DELETE | CHAR_BACK_SPACE | CHAR_ERASE_CHARACTER => {
-            session.data.pop();
+    /// Returns whether processing incoming data is paused, see `pause_input`
+    pub fn is_input_paused(&self) -> bool {
+        self.is_paused
+    }
 
-            if session.is_echoing {
-                /* Return fake backspace on echo mode */
-                return Some(vec![CHAR_BACK_SPACE, b' ', CHAR_BACK_SPACE]);
+    /// Processes the buffered incoming data, queueing the responses
+    fn process_stream(&mut self) {
+        while let Some(next) = dequeue(&mut self.stream) {
+            if self.fatal_error.is_some() {
+                /* The connection is about to be closed, nothing is processed anymore. */
+                self.stream.clear();
+                return;
             }
-        }
-        CHAR_ERASE_LINE => erase_current_line(&mut session.data),
-        CHAR_ESCAPE => {
-            session.state = TelnetState::AnsiEscapeSequence;
-            if let Some(buffer) = session.ansi_escape_sequence_buffer.as_mut() {
-                buffer.push(next as char);
+
+            let token = self.parser.update(next);
+
+            if let Some(error) = self.parser.take_error() {
+                self.report_protocol_error(error);
+
+                if self.fatal_error.is_some() {
+                    /* The token is discarded as well, like all further input */
+                    continue;
+                }
             }
-        }
-        _ => {
-            session.data.push(next as char);
 
-            if session.is_echoing {
-                return Some(vec![next]);
+            let (kind, result) = match token {
+                Some(TelnetToken::Data(byte)) => {
+                    let (state, result) = (self.state.update())(self, byte);
+                    self.state = state;
+                    (OutputKind::Data, result)
+                }
+                Some(TelnetToken::Command(command, option)) => {
+                    (OutputKind::Command, handle_command(self, command, option))
+                }
+                Some(TelnetToken::SubNegotiation(option, payload)) => {
+                    let result = handle_sub_negotiation(self, option, &payload);
+                    (OutputKind::Command, result)
+                }
+                None => continue,
+            };
+
+            #[cfg(feature = "mccp")]
+            let kind = match self.compression {
+                CompressionState::Starting
+                    if kind == OutputKind::Command && next == CHAR_MCCP2 && result.is_some() =>
+                {
+                    OutputKind::CompressionStart
+                }
+                CompressionState::Ending(_)
+                    if kind == OutputKind::Command && next == CHAR_MCCP2 && result.is_some() =>
+                {
+                    OutputKind::CompressionEnd
+                }
+                _ => kind,
+            };
+
+            match (result, self.pending_output.last_mut()) {
+                /* Coalesce consecutive responses of the same kind, so e.g. the echo of
+                 * all chars is a single response that's prepared for sending at once. */
+                (Some(v), Some((last_kind, last))) if *last_kind == kind => {
+                    last.extend_from_slice(&v);
+                }
+                (Some(v), _) => self.pending_output.push((kind, v)),
+                (None, _) => {}
             }
         }
+
+        self.detect_non_telnet_client();
     }
 
-    None
-}
+    /// Decides whether the client speaks Telnet once enough bytes arrived, see
+    /// `TelnetEvent::SuspectedNonTelnetClient`. Clients that negotiated are never
+    /// suspected.
+    fn detect_non_telnet_client(&mut self) {
+        let Some(first_bytes) = self.first_bytes.as_ref() else {
+            return;
+        };
 
-/// Updates given `session` in `TelnetState::Command` based on `next` incoming byte
-///
-/// # Arguments
-///
-/// * `session` - The affected `TelnetSession`
-/// * `next` - The next incoming byte
-///
-/// # Returns
-///
-/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
-fn update_session_command(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
-    match next {
-        CHAR_WILL => session.state = TelnetState::CommandWill,
-        CHAR_WONT => session.state = TelnetState::CommandWont,
-        CHAR_DO => session.state = TelnetState::CommandDo,
-        CHAR_DONT => session.state = TelnetState::CommandDont,
-        CHAR_SUB_NEGOTIATION => session.state = TelnetState::SubNegotiation,
-        _ => error!("Not implemented command: {next}"),
-    };
+        if first_bytes.len() < NON_TELNET_DETECTION_BYTES {
+            return;
+        }
 
-    None
-}
+        /* Invalid UTF-8 counts as unprintable, so non-ASCII text isn't suspicious */
+        let unprintable: usize = first_bytes
+            .utf8_chunks()
+            .map(|chunk| {
+                let controls = chunk.valid().chars().filter(|&c| {
+                    c.is_control() && !matches!(c, '\r' | '\n' | '\t' | '\x08' | '\x1b' | '\x7f')
+                });
+                chunk.invalid().len() + controls.count()
+            })
+            .sum();
 
-/// Updates given `session` in `TelnetState::Will` based on `next` incoming byte
-///
-/// # Arguments
-///
-/// * `session` - The affected `TelnetSession`
-/// * `next` - The next incoming byte
-///
-/// # Returns
-///
-/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
-fn update_session_will(session: &mut TelnetSession, _next: u8) -> Option<Vec<u8>> {
-    /* Ignore message, just go back to idle state */
-    session.state = TelnetState::Idle;
-    None
-}
+        self.first_bytes = None;
 
-/// Updates given `session` in `TelnetState::Wont` based on `next` incoming byte
-///
+        if unprintable * 2 > NON_TELNET_DETECTION_BYTES {
+            error!("Client sent {unprintable} unprintable bytes without negotiating");
+            self.push_event(TelnetEvent::SuspectedNonTelnetClient);
+        }
+    }
+
+    /// Returns the queued command responses (e.g. negotiation replies) of `receive`,
+    /// removing them from the queue. They're in the order they were queued.
+    ///
+    /// # Returns
+    ///
+    /// The sequence that should be sent to the Telnet client.
+    pub fn take_commands(&mut self) -> Vec<u8> {
+        self.take_pending_output(|kind| kind != OutputKind::Data)
+    }
+
+    /// Returns the queued data responses (e.g. echo) of `receive`, removing them from
+    /// the queue. They're in the order they were queued.
+    ///
+    /// # Returns
+    ///
+    /// The sequence that should be sent to the Telnet client.
+    pub fn take_data(&mut self) -> Vec<u8> {
+        self.take_pending_output(|kind| kind == OutputKind::Data)
+    }
+
+    /// Returns all queued responses of `receive`, removing them from the queue. In
+    /// contrast to `take_commands` and `take_data`, commands and data are returned in
+    /// the order they were queued.
+    ///
+    /// # Returns
+    ///
+    /// The sequence that should be sent to the Telnet client.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.take_pending_output(|_| true)
+    }
+
+    /// Removes the queued responses matching `filter` and prepares them for sending,
+    /// see `prepare_output`. Output compression applies in the order the responses are
+    /// taken, so the client can decompress them as long as they're sent in that order.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - Returns whether responses of given kind should be taken
+    fn take_pending_output(&mut self, filter: impl Fn(OutputKind) -> bool) -> Vec<u8> {
+        /* Data is held back while the client paused the output via XOFF */
+        let is_output_paused = self.is_output_paused;
+        let (taken, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_output)
+            .into_iter()
+            .partition(|&(kind, _)| {
+                filter(kind) && !(is_output_paused && kind == OutputKind::Data)
+            });
+        self.pending_output = kept;
+
+        let mut output = vec![];
+
+        for (_kind, bytes) in taken {
+            #[cfg(feature = "mccp")]
+            if let (OutputKind::CompressionEnd, CompressionState::Ending(_)) =
+                (_kind, &self.compression)
+            {
+                if let CompressionState::Ending(encoder) =
+                    std::mem::replace(&mut self.compression, CompressionState::Disabled)
+                {
+                    /* Finishing the stream tells the client that uncompressed data follows.
+                     * Writing into a `Vec<u8>` can't fail. */
+                    output.extend(encoder.finish().unwrap_or_default());
+                }
+            }
+
+            output.extend_from_slice(&self.prepare_output(&bytes));
+
+            #[cfg(feature = "mccp")]
+            if let (OutputKind::CompressionStart, CompressionState::Starting) =
+                (_kind, &self.compression)
+            {
+                /* The `IAC SB MCCP2 IAC SE` marker itself is sent uncompressed. */
+                self.compression =
+                    CompressionState::Active(ZlibEncoder::new(vec![], Compression::default()));
+            }
+        }
+
+        output
+    }
+
+    /// Accepts incoming text, see `accept_data`. Useful for tests and for feeding known
+    /// text. A `&str` is valid UTF-8, which never contains the byte `0xFF`, so `s` can't
+    /// contain an `IAC` and is never mistaken for a command. Other control chars (e.g.
+    /// backspace or escape) are still interpreted.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - Incoming text
+    ///
+    /// # Returns
+    ///
+    /// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_str("admin");
+    ///
+    /// assert_eq!(telnet_session.data_bytes(), b"admin");
+    /// ```
+    pub fn accept_str(&mut self, s: &str) -> Option<Vec<u8>> {
+        self.accept_data(s.as_bytes())
+    }
+
+    /// Drives this session from any transport: Reads chunks from `reader`, passes them
+    /// to `accept_data` and writes the responses to `writer`. This blocks until `reader`
+    /// reaches its end or a fatal protocol error occurred (see `protocol_error`).
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Incoming data, e.g. a `TcpStream` or an in-memory `Cursor`
+    /// * `writer` - Receives everything that should be sent to the Telnet client
+    ///
+    /// # Returns
+    ///
+    /// An error if reading or writing failed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// let mut output = vec![];
+    /// telnet_session
+    ///     .run_blocking(Cursor::new(b"admin\r\n"), &mut output)
+    ///     .unwrap();
+    ///
+    /// assert_eq!(telnet_session.data_bytes(), b"admin\r\n");
+    /// ```
+    pub fn run_blocking<R: Read, W: Write>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+    ) -> std::io::Result<()> {
+        let mut buffer = [0; READ_CHUNK_SIZE];
+
+        loop {
+            let read_bytes = match reader.read(&mut buffer) {
+                Ok(0) => return writer.flush(),
+                Ok(c) => c,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            if let Some(response) = self.accept_data(&buffer[..read_bytes]) {
+                writer.write_all(response.as_slice())?;
+            }
+
+            if self.fatal_error.is_some() {
+                return writer.flush();
+            }
+        }
+    }
+
+    /// Returns the current state of the session, e.g. for diagnostics. This has no
+    /// effect on the session.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::{ParserState, TelnetSession};
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_data(&[255, 250]); // IAC SB
+    ///
+    /// assert_eq!(telnet_session.state(), ParserState::SubNegotiation);
+    /// ```
+    pub fn state(&self) -> ParserState {
+        match (self.parser.state(), self.state) {
+            #[cfg(feature = "ansi")]
+            (ParserState::Idle, TelnetState::AnsiEscapeSequence) => ParserState::AnsiEscapeSequence,
+            (state, _) => state,
+        }
+    }
+
+    /// Returns whether the session received the start of a command (e.g. a lone `IAC`
+    /// or an unfinished sub negotiation) that it's still waiting to complete. Servers
+    /// may use this to wait for more input before e.g. acting on a prompt.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_data(&[255, 253]); // IAC DO
+    /// assert!(telnet_session.has_partial_command());
+    ///
+    /// telnet_session.accept_data(&[1]); // ECHO
+    /// assert!(!telnet_session.has_partial_command());
+    /// ```
+    pub fn has_partial_command(&self) -> bool {
+        self.parser.state() != ParserState::Idle
+    }
+
+    /// Returns the number of received bytes that are buffered until they're complete:
+    /// The bytes of a partial command (sub negotiation parameters are counted
+    /// unescaped), of a partial ANSI escape sequence and of a partial UTF-8 char that's
+    /// held back from echoing. These bytes aren't part of the data buffer (yet).
+    pub fn pending_bytes(&self) -> usize {
+        let command_bytes = self.parser.pending_bytes();
+
+        #[cfg(feature = "ansi")]
+        let escape_sequence_bytes = match self.state {
+            TelnetState::AnsiEscapeSequence => self.escape_sequence.len(),
+            _ => 0,
+        };
+        #[cfg(not(feature = "ansi"))]
+        let escape_sequence_bytes = 0;
+
+        self.stream.len() + command_bytes + escape_sequence_bytes + self.echo_utf8_buffer.len()
+    }
+
+    /// Returns currently read (non-command) data from data stream
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let telnet_session = TelnetSession::create(false);
+    ///
+    /// // data (e.g. a user-name) is sent from client...
+    ///
+    /// let data = telnet_session.get_data_buffer();
+    /// let message = data.iter().collect::<String>();
+    /// println!("{}", message); // "admin"
+    /// ```
+    ///
+    /// Every byte is mapped to a single `char`, so non-ASCII data is not decoded.
+    /// Use `data_bytes` to read exactly what arrived.
+    pub fn get_data_buffer(&self) -> &Vec<char> {
+        &self.data
+    }
+
+    /// Returns the raw bytes of currently read (non-command) data from data stream. In
+    /// contrast to `get_data_buffer`, this is binary-safe. Escaped bytes (`IAC IAC`) are
+    /// already unescaped. NUL bytes are stripped by default, see `set_nul_handling`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_data(&[2, 255, 255, 1]);
+    ///
+    /// assert_eq!(telnet_session.data_bytes(), [2, 255, 1]);
+    /// ```
+    pub fn data_bytes(&self) -> &[u8] {
+        &self.data_bytes
+    }
+
+    /// Clears currently read (non-command) data from buffer.
+    /// This can be useful if you already handled the data and don't need it anymore.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    ///
+    /// // data (e.g. a user-name) is sent from client...
+    ///
+    /// let data = telnet_session.get_data_buffer();
+    /// let message = data.iter().collect::<String>();
+    /// println!("{}", message); // "admin"
+    ///
+    /// telnet_session.clear_data_buffer();
+    ///
+    /// let data = telnet_session.get_data_buffer();
+    /// assert!(data.is_empty());
+    /// ```
+    pub fn clear_data_buffer(&mut self) {
+        self.data.clear();
+        self.data_bytes.clear();
+    }
+
+    /// Removes the first completed line from the data buffer. Lines may be terminated
+    /// by `\r\n`, `\n` or a bare `\r`.
+    ///
+    /// # Returns
+    ///
+    /// The line (without its line break), decoded with the encoding of the session, or
+    /// `None` if there's no completed line.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_data(b"look\r\nno");
+    ///
+    /// assert_eq!(telnet_session.take_line(), Some("look".to_string()));
+    /// assert_eq!(telnet_session.take_line(), None);
+    /// assert_eq!(telnet_session.data_bytes(), b"no");
+    /// ```
+    pub fn take_line(&mut self) -> Option<String> {
+        let end = self
+            .data_bytes
+            .iter()
+            .position(|&b| b == b'\r' || b == b'\n')?;
+        let line_break_length = match self.data_bytes[end..] {
+            [b'\r', b'\n', ..] => 2,
+            _ => 1,
+        };
+
+        let line = self.encoding.decode(&self.data_bytes[..end]);
+        self.data_bytes.drain(..end + line_break_length);
+        self.data.drain(..end + line_break_length);
+
+        Some(line)
+    }
+
+    /// Returns an iterator that removes all completed lines from the data buffer, see
+    /// `take_line`. A partial line is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_data(b"north\r\nsouth\nwe");
+    ///
+    /// for line in telnet_session.lines() {
+    ///     println!("Going {line}");
+    /// }
+    /// assert_eq!(telnet_session.data_bytes(), b"we");
+    /// ```
+    pub fn lines(&mut self) -> impl Iterator<Item = String> + '_ {
+        std::iter::from_fn(move || self.take_line())
+    }
+
+    /// Prepares outgoing data so that it can be sent to the Telnet client. If MCCP2
+    /// compression is active, `data` is deflated, otherwise it's returned unchanged.
+    /// Every byte that is sent to the client (e.g. application messages) should pass
+    /// through this function. Responses of `accept_data` already did.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data that should be sent to the client
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    ///
+    /// // compression was not negotiated, so data is unchanged
+    /// assert_eq!(telnet_session.prepare_output(b"Hello"), b"Hello");
+    /// ```
+    pub fn prepare_output(&mut self, data: &[u8]) -> Vec<u8> {
+        let converted;
+        let data = if self.is_converting_output_line_breaks {
+            converted = convert_output_line_breaks(data, &mut self.is_output_after_carriage_return);
+            &converted[..]
+        } else {
+            data
+        };
+
+        self.record_trace(">", data);
+
+        #[cfg(feature = "mccp")]
+        if let CompressionState::Active(encoder) | CompressionState::Ending(encoder) =
+            &mut self.compression
+        {
+            /* Writing into a `Vec<u8>` can't fail. Flushing performs a sync flush, so the
+             * client is able to decompress everything that was sent so far. */
+            encoder.write_all(data).unwrap_or_default();
+            encoder.flush().unwrap_or_default();
+            return std::mem::take(encoder.get_mut());
+        }
+
+        data.to_vec()
+    }
+
+    /// Sets whether lone `\n` line breaks of outgoing data are converted to `\r\n`, so
+    /// clients don't render them as a line feed without carriage return ("staircase"
+    /// output). Command sequences (e.g. `IAC SB ... IAC SE`) are left unchanged.
+    /// Defaults to false.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether line breaks should be converted, see `prepare_output`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_output_crlf(true);
+    ///
+    /// assert_eq!(telnet_session.prepare_output(b"a\nb\r\n"), b"a\r\nb\r\n");
+    /// ```
+    pub fn set_output_crlf(&mut self, enabled: bool) {
+        self.is_converting_output_line_breaks = enabled;
+    }
+
+    /// Offers MCCP2 (option 86) output compression to the client. Compression starts
+    /// as soon as the client agrees with `IAC DO MCCP2`.
+    ///
+    /// # Returns
+    ///
+    /// The `IAC WILL MCCP2` sequence that should be sent to the Telnet client.
+    #[cfg(feature = "mccp")]
+    pub fn offer_compression(&mut self) -> Vec<u8> {
+        if let CompressionState::Disabled = self.compression {
+            self.compression = CompressionState::Offered;
+        }
+
+        vec![CHAR_IAC, CHAR_WILL, CHAR_MCCP2]
+    }
+
+    /// Returns whether outgoing data is currently compressed using MCCP2
+    #[cfg(feature = "mccp")]
+    pub fn compression_active(&self) -> bool {
+        matches!(
+            self.compression,
+            CompressionState::Active(_) | CompressionState::Ending(_)
+        )
+    }
+
+    /// Returns the sequence that marks the end of a record (e.g. a prompt). This is
+    /// `IAC EOR` if the client agreed to END-OF-RECORD (option 25), `IAC GA` otherwise.
+    ///
+    /// # Returns
+    ///
+    /// The sequence that should be sent to the Telnet client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    ///
+    /// // END-OF-RECORD was not negotiated, so IAC GA is used
+    /// assert_eq!(telnet_session.end_record(), [255, 249]);
+    /// ```
+    pub fn end_record(&mut self) -> Vec<u8> {
+        let end_of_record = if self.is_end_of_record_active {
+            [CHAR_IAC, CHAR_EOR]
+        } else {
+            [CHAR_IAC, CHAR_GO_AHEAD]
+        };
+
+        self.prepare_output(&end_of_record)
+    }
+
+    /// Returns a prompt (e.g. `login: `) that signals the client that input is expected.
+    /// The text is followed by `IAC EOR` if the client agreed to END-OF-RECORD (option
+    /// 25), by nothing if the session suppresses GO AHEAD (option 3) and by `IAC GA`
+    /// otherwise. A `&str` never contains the byte `0xFF`, so the text needs no `IAC`
+    /// escaping.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The prompt, usually without a trailing line break
+    ///
+    /// # Returns
+    ///
+    /// The sequence that should be sent to the Telnet client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    ///
+    /// assert_eq!(telnet_session.prompt("> "), b"> \xFF\xF9"); // IAC GA
+    /// ```
+    pub fn prompt(&mut self, text: &str) -> Vec<u8> {
+        let mut prompt = text.as_bytes().to_vec();
+
+        if self.is_end_of_record_active {
+            prompt.extend_from_slice(&[CHAR_IAC, CHAR_EOR]);
+        } else if !self.is_suppressing_go_ahead {
+            prompt.extend_from_slice(&[CHAR_IAC, CHAR_GO_AHEAD]);
+        }
+
+        self.prepare_output(&prompt)
+    }
+
+    /// Negotiates down all currently enabled options, so the terminal of the client
+    /// returns to a sane state, e.g. before the server closes the connection. Options of
+    /// the session (e.g. ECHO) are disabled by `IAC WONT option`, options of the client
+    /// (e.g. NAWS) by `IAC DONT option`. MCCP2 compression is not affected.
+    ///
+    /// # Returns
+    ///
+    /// The sequence that should be sent to the Telnet client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_data(&[255, 253, 1]); // IAC DO ECHO
+    ///
+    /// assert_eq!(telnet_session.teardown(), [255, 252, 1]); // IAC WONT ECHO
+    /// ```
+    pub fn teardown(&mut self) -> Vec<u8> {
+        let mut teardown = vec![];
+        let local_options = [
+            (&mut self.is_echoing, CHAR_ECHO),
+            (&mut self.is_suppressing_go_ahead, CHAR_SUPPRESS_GO_AHEAD),
+            (&mut self.is_end_of_record_active, CHAR_END_OF_RECORD),
+            (&mut self.is_sending_terminal_type, CHAR_TERMINAL_TYPE),
+        ];
+
+        for (is_enabled, option) in local_options {
+            if std::mem::take(is_enabled) {
+                teardown.extend_from_slice(&iac_command(CHAR_WONT, option));
+            }
+        }
+
+        let mut remote_options: Vec<u8> = self.remote_options.drain().collect();
+        remote_options.sort_unstable();
+
+        for option in remote_options {
+            teardown.extend_from_slice(&iac_command(CHAR_DONT, option));
+        }
+
+        self.prepare_output(&teardown)
+    }
+
+    /// Sets a char that is echoed instead of printable incoming chars, e.g. `*` for
+    /// password prompts. The data buffer still contains the real chars. `None` disables
+    /// masking. This only has an effect if the session is echoing.
+    ///
+    /// # Arguments
+    ///
+    /// * `mask` - The char that should be echoed, if any
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_data(&[255, 253, 1]); // IAC DO ECHO
+    /// telnet_session.set_echo_mask(Some('*'));
+    ///
+    /// assert_eq!(telnet_session.accept_data(b"pw"), Some(b"**".to_vec()));
+    /// ```
+    pub fn set_echo_mask(&mut self, mask: Option<char>) {
+        self.echo_mask = mask;
+    }
+
+    /// Sets whether incoming control chars are echoed in caret notation, like terminals
+    /// display them (e.g. `^A` for Ctrl-A). The data buffer still contains the real
+    /// chars. Line breaks and tabs are echoed as they are. Defaults to false.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether control chars should be echoed in caret notation
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_caret_echo(true);
+    /// telnet_session.accept_data(&[255, 253, 1]); // IAC DO ECHO
+    ///
+    /// assert_eq!(telnet_session.accept_data(&[3]), Some(b"^C".to_vec()));
+    /// ```
+    pub fn set_caret_echo(&mut self, enabled: bool) {
+        self.is_caret_echo = enabled;
+    }
+
+    /// Sets whether unknown ANSI escape sequences are answered by BEL. A sequence is
+    /// unknown if it isn't terminated by a known final byte before exceeding the escape
+    /// sequence buffer limit, see `BufferLimits::escape_sequence`. Known sequences are
+    /// consumed silently (unless they're passed on). Defaults to false.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether unknown escape sequences should be answered by BEL
+    #[cfg(feature = "ansi")]
+    pub fn set_bell_on_unknown_escape(&mut self, enabled: bool) {
+        self.is_bell_on_unknown_escape = enabled;
+    }
+
+    /// Sets whether lines are edited server-side, e.g. for clients that refuse
+    /// LINEMODE. The session echoes every char and the client is asked to send them
+    /// one at a time. Within the current line, chars are inserted at the cursor,
+    /// backspace erases the char left of it and Ctrl-U erases the whole line. With the
+    /// `ansi` feature, the cursor is moved by the arrow keys, Home and End. The cursor
+    /// assumes a single column per byte.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether lines should be edited server-side
+    ///
+    /// # Returns
+    ///
+    /// The negotiation that should be sent to the Telnet client. Enabling sends `IAC
+    /// WILL ECHO` and `IAC WILL SUPPRESS-GO-AHEAD` unless they're already active.
+    /// Disabling sends `IAC WONT ECHO` only if enabling turned the echo on, so an echo
+    /// the client asked for (`IAC DO ECHO`) is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_server_side_line_editing(true);
+    ///
+    /// assert_eq!(telnet_session.accept_data(b"ab\x08c"), Some(b"ab\x08 \x08c".to_vec()));
+    /// assert_eq!(telnet_session.data_bytes(), b"ac");
+    /// ```
+    pub fn set_server_side_line_editing(&mut self, enabled: bool) -> Vec<u8> {
+        self.is_editing_lines = enabled;
+        self.edit_cursor_offset = 0;
+
+        if !enabled {
+            if std::mem::take(&mut self.is_echo_by_line_editing) && self.is_echoing {
+                return self.send_command(CHAR_WONT, CHAR_ECHO);
+            }
+
+            return vec![];
+        }
+
+        let mut negotiation = vec![];
+
+        if !self.is_echoing {
+            self.is_echo_by_line_editing = true;
+            negotiation.extend(self.send_command(CHAR_WILL, CHAR_ECHO));
+        }
+
+        if !self.is_suppressing_go_ahead {
+            negotiation.extend(self.send_command(CHAR_WILL, CHAR_SUPPRESS_GO_AHEAD));
+        }
+
+        negotiation
+    }
+
+    /// Sets a policy that decides whether options the client asks for are accepted. It's
+    /// called with the option and the received command before the session replies.
+    /// Only `Negotiation::Do` and `Negotiation::Will` are asked, as disabling an option
+    /// can't be refused. If the policy returns false, the option is refused (`WONT` or
+    /// `DONT`). Unknown options the client offers (`WILL`) are accepted via `DO` if the
+    /// policy approves them, so the application can handle their sub negotiations. The
+    /// session never agrees to perform options it doesn't implement, so `DO` for them is
+    /// always answered by `WONT`.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - Returns whether given option and command should be accepted
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::{Negotiation, TelnetSession};
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_option_policy(|option, negotiation| {
+    ///     option == 3 && negotiation == Negotiation::Do
+    /// });
+    ///
+    /// assert_eq!(telnet_session.accept_data(&[255, 253, 3]), Some(vec![255, 251, 3]));
+    /// ```
+    pub fn set_option_policy(
+        &mut self,
+        policy: impl Fn(u8, Negotiation) -> bool + Send + Sync + 'static,
+    ) {
+        self.option_policy = Some(Box::new(policy));
+    }
+
+    /// Sets a fallback that handles sub negotiations of options the session doesn't
+    /// implement itself. Without a fallback, these sub negotiations are logged and
+    /// discarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `fallback` - Called with the option and the unescaped payload. If it returns
+    ///   `Some(Vec<u8>)`, that's sent to the Telnet client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_subneg_fallback(|option, payload| {
+    ///     println!("Option {option} sent {payload:?}");
+    ///     None
+    /// });
+    /// ```
+    pub fn set_subneg_fallback(
+        &mut self,
+        fallback: impl FnMut(u8, &[u8]) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) {
+        self.sub_negotiation_fallback = Some(Box::new(fallback));
+    }
+
+    /// Sets a handler of commands the session doesn't implement itself (e.g.
+    /// `IAC AYT`). Without a handler, these commands are logged and discarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - Called with the command byte. If it returns `Some(Vec<u8>)`,
+    ///   that's sent to the Telnet client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_unknown_command_handler(|command| match command {
+    ///     246 => Some(b"[Yes]".to_vec()),
+    ///     _ => None,
+    /// });
+    ///
+    /// assert_eq!(telnet_session.accept_data(&[255, 246]), Some(b"[Yes]".to_vec()));
+    /// ```
+    pub fn set_unknown_command_handler(
+        &mut self,
+        handler: impl FnMut(u8) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) {
+        self.unknown_command_handler = Some(Box::new(handler));
+    }
+
+    /// Returns whether the session echoes incoming data (local echo), as requested by
+    /// the client (`IAC DO ECHO`). It's disabled by `IAC DONT ECHO` only.
+    pub fn is_echoing(&self) -> bool {
+        self.is_echoing
+    }
+
+    /// Returns whether the client echoes the data it receives from the session, as
+    /// offered by the client (`IAC WILL ECHO`). It's disabled by `IAC WONT ECHO` only,
+    /// which doesn't affect the local echo, see `is_echoing`.
+    pub fn is_client_echoing(&self) -> bool {
+        self.remote_options.contains(&CHAR_ECHO)
+    }
+
+    /// Adds a filter that transforms every completed line (without its line break)
+    /// before it's stored in the data buffer and surfaced as `TelnetEvent::Line`, e.g.
+    /// to trim it. Multiple filters run in registration order. The echo is not
+    /// affected.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - Called with the raw line, which may be changed in place
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.add_input_filter(|line| line.retain(|&c| c != b' '));
+    /// telnet_session.accept_data(b"a b c\r\n");
+    ///
+    /// assert_eq!(telnet_session.data_bytes(), b"abc\r\n");
+    /// ```
+    pub fn add_input_filter(&mut self, filter: impl FnMut(&mut Vec<u8>) + Send + Sync + 'static) {
+        self.input_filters.push(Box::new(filter));
+    }
+
+    /// Applies the input filters to the current line of the data buffer
+    fn filter_current_line(&mut self) {
+        let start = self.data_bytes.len() - self.current_line_length();
+        let mut line = self.data_bytes.split_off(start);
+        self.data.truncate(start);
+
+        for filter in &mut self.input_filters {
+            filter(&mut line);
+        }
+
+        self.push_data(&line);
+    }
+
+    /// Returns whether the client enabled given option on its side, meaning it offered
+    /// it (`IAC WILL option`) and the session agreed (`IAC DO option`).
+    ///
+    /// # Arguments
+    ///
+    /// * `option` - The option, e.g. `CHAR_NAWS`
+    pub fn is_remote_option_enabled(&self, option: u8) -> bool {
+        self.remote_options.contains(&option)
+    }
+
+    /// Sends a negotiation command (`IAC verb option`) on behalf of the server, e.g. for
+    /// scripted bots. The option state is updated as if the session initiated the
+    /// negotiation itself: `DO` requests the option, `DONT` disables it on the client's
+    /// side, `WILL` and `WONT` enable or disable the options the session implements
+    /// locally (e.g. ECHO).
+    ///
+    /// # Arguments
+    ///
+    /// * `verb` - The negotiation command, e.g. `CHAR_DO`
+    /// * `option` - The negotiated option, e.g. `CHAR_NAWS`
+    ///
+    /// # Returns
+    ///
+    /// The command sequence that should be sent to the Telnet client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::constants::{CHAR_DO, CHAR_NAWS, CHAR_WILL};
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// assert_eq!(telnet_session.send_command(CHAR_DO, CHAR_NAWS), [255, 253, 31]);
+    ///
+    /// // The client agreed to the request, which needs no reply
+    /// assert_eq!(telnet_session.accept_data(&[255, CHAR_WILL, CHAR_NAWS]), None);
+    /// assert!(telnet_session.is_remote_option_enabled(CHAR_NAWS));
+    /// ```
+    pub fn send_command(&mut self, verb: u8, option: u8) -> Vec<u8> {
+        match (verb, option) {
+            (CHAR_DO, _) if !self.remote_options.contains(&option) => self.mark_requested(option),
+            (CHAR_DONT, _) => {
+                self.requested_options.remove(&option);
+                self.remote_options.remove(&option);
+            }
+            (CHAR_WILL | CHAR_WONT, CHAR_ECHO) => self.is_echoing = verb == CHAR_WILL,
+            (CHAR_WILL | CHAR_WONT, CHAR_SUPPRESS_GO_AHEAD) => {
+                self.is_suppressing_go_ahead = verb == CHAR_WILL;
+            }
+            (CHAR_WILL | CHAR_WONT, CHAR_END_OF_RECORD) => {
+                self.is_end_of_record_active = verb == CHAR_WILL;
+            }
+            (CHAR_WILL | CHAR_WONT, CHAR_TERMINAL_TYPE) => {
+                self.is_sending_terminal_type = verb == CHAR_WILL;
+            }
+            _ => {}
+        }
+
+        self.prepare_output(&iac_command(verb, option))
+    }
+
+    /// Sends a sub negotiation (`IAC SB option payload IAC SE`) on behalf of the
+    /// server, e.g. for scripted bots. `IAC` bytes of `payload` are escaped. The option
+    /// state isn't changed, options should be negotiated via `send_command` first.
+    ///
+    /// # Arguments
+    ///
+    /// * `option` - The negotiated option
+    /// * `payload` - The unescaped sub negotiation parameters
+    ///
+    /// # Returns
+    ///
+    /// The sub negotiation sequence that should be sent to the Telnet client.
+    pub fn send_subneg(&mut self, option: u8, payload: &[u8]) -> Vec<u8> {
+        self.prepare_output(&iac_subneg(option, payload))
+    }
+
+    /// Marks given option as requested from the client (`IAC DO option`), so its
+    /// answer is handled accordingly and a missing answer times out, see `tick`
+    ///
+    /// # Arguments
+    ///
+    /// * `option` - The requested option
+    fn mark_requested(&mut self, option: u8) {
+        match option {
+            CHAR_NEW_ENVIRON => self.is_environment_requested = true,
+            CHAR_TERMINAL_TYPE => self.is_terminal_type_requested = true,
+            CHAR_X_DISPLAY_LOCATION => self.is_x_display_location_requested = true,
+            CHAR_TERMINAL_SPEED => self.is_terminal_speed_requested = true,
+            _ => {}
+        }
+
+        self.requested_options.insert(option, Instant::now());
+    }
+
+    /// Returns whether the option policy refuses given option and command, see
+    /// `set_option_policy`
+    fn is_option_refused(&self, option: u8, negotiation: Negotiation) -> bool {
+        self.option_policy
+            .as_ref()
+            .is_some_and(|policy| !policy(option, negotiation))
+    }
+
+    /// Sets the maximum length of a single line. Once the current line reaches it,
+    /// further printable chars are rejected until the line is completed or shortened
+    /// (e.g. by backspace). If the session is echoing, every rejected char is answered
+    /// by BEL.
+    ///
+    /// # Arguments
+    ///
+    /// * `max` - The maximum number of bytes per line, `None` for no limit
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_max_line_length(Some(3));
+    /// telnet_session.accept_data(b"abcd");
+    ///
+    /// assert_eq!(telnet_session.data_bytes(), b"abc");
+    /// ```
+    pub fn set_max_line_length(&mut self, max: Option<usize>) {
+        self.max_line_length = max;
+    }
+
+    /// Returns the number of bytes of the current, not yet completed line in the data
+    /// buffer
+    fn current_line_length(&self) -> usize {
+        self.data_bytes
+            .iter()
+            .rev()
+            .take_while(|&&b| b != b'\r' && b != b'\n')
+            .count()
+    }
+
+    /// Sets the width of tab stops. If set, incoming tabs are expanded to spaces up to
+    /// the next tab stop of the current line, in the data buffer and in the echo. If
+    /// `None`, tabs are passed through.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - The distance between two tab stops, e.g. 8
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_tab_stops(Some(8));
+    /// telnet_session.accept_data(b"id\tname");
+    ///
+    /// assert_eq!(telnet_session.data_bytes(), b"id      name");
+    /// ```
+    pub fn set_tab_stops(&mut self, width: Option<usize>) {
+        self.tab_stops = width;
+    }
+
+    /// Sets how incoming NUL bytes are handled. The network virtual terminal sends
+    /// `CR NUL` for a carriage return without line feed, other NUL bytes are no-ops.
+    /// Defaults to `NulHandling::Strip`.
+    ///
+    /// # Arguments
+    ///
+    /// * `handling` - The handling of NUL bytes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::{NulHandling, TelnetSession};
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_nul_handling(NulHandling::LineFeed);
+    /// telnet_session.accept_data(b"a\r\0b");
+    ///
+    /// assert_eq!(telnet_session.data_bytes(), b"a\r\nb");
+    /// ```
+    pub fn set_nul_handling(&mut self, handling: NulHandling) {
+        self.nul_handling = handling;
+    }
+
+    /// Returns the flags of the LINEMODE (option 34) MODE that the client reported, e.g.
+    /// EDIT (1) or TRAPSIG (2). If EDIT is set, the client edits lines locally.
+    pub fn linemode_flags(&self) -> u8 {
+        self.linemode_flags
+    }
+
+    /// Requests the environment variables (e.g. `USER`) of the client via NEW-ENVIRON
+    /// (option 39). Once the client agrees, the variables are requested and can be read
+    /// via `environment`.
+    ///
+    /// # Returns
+    ///
+    /// The `IAC DO NEW-ENVIRON` sequence that should be sent to the Telnet client.
+    pub fn request_environment(&mut self) -> Vec<u8> {
+        self.mark_requested(CHAR_NEW_ENVIRON);
+        self.prepare_output(&[CHAR_IAC, CHAR_DO, CHAR_NEW_ENVIRON])
+    }
+
+    /// Returns the environment variables that the client reported via NEW-ENVIRON.
+    /// Both well-known variables (VAR) and user variables (USERVAR) are included.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.request_environment();
+    /// telnet_session.accept_data(&[255, 251, 39]); // IAC WILL NEW-ENVIRON
+    ///
+    /// // IAC SB NEW-ENVIRON IS VAR "USER" VALUE "admin" IAC SE
+    /// telnet_session.accept_data(&[255, 250, 39, 0, 0]);
+    /// telnet_session.accept_data(b"USER\x01admin");
+    /// telnet_session.accept_data(&[255, 240]);
+    ///
+    /// let user = telnet_session.environment().get("USER");
+    /// assert_eq!(user.map(|u| u.as_str()), Some("admin"));
+    /// ```
+    pub fn environment(&self) -> &HashMap<String, String> {
+        &self.environment
+    }
+
+    /// Returns the encoding that incoming text (e.g. `TelnetEvent::Line`) is decoded with
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Sets the encoding that incoming text (e.g. `TelnetEvent::Line`) is decoded with.
+    /// Defaults to `Encoding::Latin1`.
+    ///
+    /// # Arguments
+    ///
+    /// * `encoding` - The new encoding
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Sets whether the encoding is detected from the environment of the client. If
+    /// enabled (the default), a supported charset of the locale in `LC_ALL` or `LANG`
+    /// (e.g. `en_US.UTF-8`) sets the encoding once the client reports its environment.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether the encoding should be detected
+    pub fn set_charset_detection(&mut self, enabled: bool) {
+        self.is_charset_detection_enabled = enabled;
+    }
+
+    /// Requests the terminal type of the client via TTYPE (option 24). Once the client
+    /// agrees, the server cycles through all terminal types of the client, including
+    /// its MTTS capabilities.
+    ///
+    /// # Returns
+    ///
+    /// The `IAC DO TTYPE` sequence that should be sent to the Telnet client.
+    pub fn request_terminal_type(&mut self) -> Vec<u8> {
+        self.mark_requested(CHAR_TERMINAL_TYPE);
+        self.prepare_output(&[CHAR_IAC, CHAR_DO, CHAR_TERMINAL_TYPE])
+    }
+
+    /// Sets the backend that decides about the authentication of the client via
+    /// AUTHENTICATION (option 37). Without a backend, the client's offer to
+    /// authenticate is refused.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The backend, see `AuthBackend`
+    pub fn set_auth_backend(&mut self, backend: impl AuthBackend + Send + Sync + 'static) {
+        self.auth_backend = Some(Box::new(backend));
+    }
+
+    /// Asks the client to authenticate via AUTHENTICATION (option 37). Once the client
+    /// agrees, it's sent the authentication types of the auth backend, see
+    /// `set_auth_backend`.
+    ///
+    /// # Returns
+    ///
+    /// The `IAC DO AUTHENTICATION` sequence that should be sent to the Telnet client.
+    pub fn request_authentication(&mut self) -> Vec<u8> {
+        self.is_authentication_requested = true;
+        self.prepare_output(&[CHAR_IAC, CHAR_DO, CHAR_AUTHENTICATION])
+    }
+
+    /// Returns whether the auth backend accepted the authentication data of the client
+    pub fn is_authenticated(&self) -> bool {
+        self.is_authenticated
+    }
+
+    /// Returns the terminal types the client reported via TTYPE, in the order they
+    /// were reported. Usually, the first one is the client name and the second one
+    /// the actual terminal type.
+    pub fn terminal_types(&self) -> &[String] {
+        &self.terminal_types
+    }
+
+    /// Returns the capabilities the client reported via MTTS, if any
+    pub fn terminal_capabilities(&self) -> Option<TerminalCapabilities> {
+        self.terminal_capabilities
+    }
+
+    /// Sets the terminal type that's sent to clients asking for the terminal type of the
+    /// server (reverse TTYPE, `IAC DO TTYPE`). Defaults to `UNKNOWN`.
+    ///
+    /// # Arguments
+    ///
+    /// * `terminal_type` - The terminal type of the server
+    pub fn set_server_terminal_type(&mut self, terminal_type: &str) {
+        self.server_terminal_type = terminal_type.to_string();
+    }
+
+    /// Requests the X display location of the client via XDISPLOC (option 35). Once
+    /// the client agrees, the location is requested.
+    ///
+    /// # Returns
+    ///
+    /// The `IAC DO XDISPLOC` sequence that should be sent to the Telnet client.
+    pub fn request_x_display_location(&mut self) -> Vec<u8> {
+        self.mark_requested(CHAR_X_DISPLAY_LOCATION);
+        self.prepare_output(&[CHAR_IAC, CHAR_DO, CHAR_X_DISPLAY_LOCATION])
+    }
+
+    /// Returns the X display location the client reported via XDISPLOC (e.g.
+    /// `host:0.0`), if any
+    pub fn x_display_location(&self) -> Option<&str> {
+        self.x_display_location.as_deref()
+    }
+
+    /// Returns the location the client reported via SEND-LOCATION (option 23), if
+    /// any. Clients that offer SEND-LOCATION are accepted and send their location
+    /// right away.
+    pub fn client_location(&self) -> Option<&str> {
+        self.client_location.as_deref()
+    }
+
+    /// Requests the terminal speed of the client via TSPEED (option 32). Once the
+    /// client agrees, the speed is requested.
+    ///
+    /// # Returns
+    ///
+    /// The `IAC DO TSPEED` sequence that should be sent to the Telnet client.
+    pub fn request_terminal_speed(&mut self) -> Vec<u8> {
+        self.mark_requested(CHAR_TERMINAL_SPEED);
+        self.prepare_output(&[CHAR_IAC, CHAR_DO, CHAR_TERMINAL_SPEED])
+    }
+
+    /// Returns the terminal speed (transmit, receive) in bits per second the client
+    /// reported via TSPEED, if any
+    pub fn terminal_speed(&self) -> Option<(u32, u32)> {
+        self.terminal_speed
+    }
+
+    /// Returns the window size (width, height) the client reported via NAWS, if NAWS
+    /// is enabled. NAWS has to be requested (see `TelnetSessionBuilder::request`) or
+    /// approved by the option policy.
+    pub fn get_window_size(&self) -> Option<(u16, u16)> {
+        self.window_size
+    }
+
+    /// Returns whether flow control is enabled via LFLOW (option 33): The option is
+    /// enabled and the client didn't turn flow control off (`IAC SB LFLOW OFF IAC SE`).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::constants::{CHAR_TOGGLE_FLOW_CONTROL, CHAR_WILL};
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_data(&[255, CHAR_WILL, CHAR_TOGGLE_FLOW_CONTROL]);
+    /// assert!(telnet_session.is_flow_control_enabled());
+    ///
+    /// telnet_session.accept_data(&[255, 250, CHAR_TOGGLE_FLOW_CONTROL, 0, 255, 240]);
+    /// assert!(!telnet_session.is_flow_control_enabled());
+    /// ```
+    pub fn is_flow_control_enabled(&self) -> bool {
+        self.is_flow_control_on && self.remote_options.contains(&CHAR_TOGGLE_FLOW_CONTROL)
+    }
+
+    /// Returns the way output that was stopped by XOFF is restarted, as reported via
+    /// LFLOW (option 33), if any
+    pub fn flow_control_restart(&self) -> Option<FlowControlRestart> {
+        self.flow_control_restart
+    }
+
+    /// Returns whether the client stopped the output via XOFF, so output should be
+    /// held until it's restarted (XON, or any char with `FlowControlRestart::Any`).
+    /// XON and XOFF are only interpreted while flow control is enabled, see
+    /// `is_flow_control_enabled`, and aren't part of the data buffer. Meanwhile, queued
+    /// data responses (e.g. echo) are held back, only command responses are returned.
+    /// Output of the application has to be held back by the application.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::constants::{CHAR_TOGGLE_FLOW_CONTROL, CHAR_WILL, CHAR_XOFF};
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_data(&[255, CHAR_WILL, CHAR_TOGGLE_FLOW_CONTROL]);
+    /// telnet_session.accept_data(&[CHAR_XOFF]);
+    ///
+    /// assert!(telnet_session.is_output_paused());
+    /// ```
+    pub fn is_output_paused(&self) -> bool {
+        self.is_output_paused
+    }
+
+    /// Returns and removes all events that occurred since the last call. Events should
+    /// be taken regularly, as the queue is limited (see `BufferLimits::events`).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::{TelnetEvent, TelnetSession};
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_data(&[255, 253, 1]); // IAC DO ECHO
+    ///
+    /// assert_eq!(
+    ///     telnet_session.take_events(),
+    ///     [TelnetEvent::Negotiation { command: 253, option: 1 }]
+    /// );
+    /// assert!(telnet_session.take_events().is_empty());
+    /// ```
+    pub fn take_events(&mut self) -> Vec<TelnetEvent> {
+        std::mem::take(&mut self.events)
+            .into_iter()
+            .map(|timed_event| timed_event.event)
+            .collect()
+    }
+
+    /// Returns and removes all events that occurred since the last call, together with
+    /// the point in time they occurred, e.g. for latency analysis. Events are only
+    /// timestamped while enabled via `set_event_timestamps`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_event_timestamps(true);
+    /// telnet_session.accept_data(&[255, 253, 1]); // IAC DO ECHO
+    ///
+    /// assert!(telnet_session.take_timed_events()[0].timestamp.is_some());
+    /// ```
+    pub fn take_timed_events(&mut self) -> Vec<TimedEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Sets whether events are timestamped when they occur, see `take_timed_events`.
+    /// Defaults to false, which avoids reading the clock for every event.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether events should be timestamped
+    pub fn set_event_timestamps(&mut self, enabled: bool) {
+        self.is_timestamping_events = enabled;
+    }
+
+    /// Sets how protocol errors of the client (e.g. malformed sub negotiations) are
+    /// handled. Defaults to `ErrorPolicy::Ignore`.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The handling of protocol errors
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::{ErrorPolicy, ProtocolError, TelnetSession};
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_error_policy(ErrorPolicy::Disconnect);
+    /// telnet_session.accept_data(&[255, 251, 255, 241]); // IAC WILL IAC NOP
+    ///
+    /// assert_eq!(
+    ///     telnet_session.protocol_error(),
+    ///     Some(ProtocolError::IncompleteNegotiation)
+    /// );
+    /// ```
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.error_policy = policy;
+    }
+
+    /// Returns the protocol error that ended the session, if the error policy is
+    /// `ErrorPolicy::Disconnect`. Once it occurred, all further input is discarded and
+    /// the connection should be closed.
+    pub fn protocol_error(&self) -> Option<ProtocolError> {
+        self.fatal_error
+    }
+
+    /// Handles a protocol error of the client based on the error policy
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - The protocol error
+    fn report_protocol_error(&mut self, error: ProtocolError) {
+        match self.error_policy {
+            ErrorPolicy::Ignore => return,
+            ErrorPolicy::Recover => {}
+            ErrorPolicy::Disconnect => self.fatal_error = Some(error),
+        }
+
+        self.push_event(TelnetEvent::ProtocolError(error));
+    }
+
+    /// Returns a keepalive sequence (`IAC NOP`) that can be sent periodically to keep
+    /// idle connections from being dropped by e.g. NAT middleboxes. The client ignores it.
+    ///
+    /// # Returns
+    ///
+    /// The sequence that should be sent to the Telnet client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    ///
+    /// if telnet_session.idle_since().elapsed().as_secs() >= 60 {
+    ///     let keepalive = telnet_session.keepalive();
+    ///     // send keepalive to the client...
+    /// }
+    /// ```
+    pub fn keepalive(&mut self) -> Vec<u8> {
+        self.prepare_output(&[CHAR_IAC, CHAR_NOP])
+    }
+
+    /// Returns a bell (`BEL`) that alerts the user, usually by an audible beep
+    ///
+    /// # Returns
+    ///
+    /// The sequence that should be sent to the Telnet client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    ///
+    /// assert_eq!(telnet_session.bell(), [7]);
+    /// ```
+    pub fn bell(&mut self) -> Vec<u8> {
+        self.prepare_output(&[CHAR_BEL])
+    }
+
+    /// Returns a visual bell that alerts the user by flashing the screen of ANSI
+    /// terminals, e.g. if an audible beep is undesirable
+    ///
+    /// # Returns
+    ///
+    /// The sequence that should be sent to the Telnet client.
+    pub fn visual_bell(&mut self) -> Vec<u8> {
+        self.prepare_output(VISUAL_BELL)
+    }
+
+    /// Marks the session as active right now. This is done automatically whenever
+    /// data is accepted, but may also be used for e.g. outgoing activity.
+    pub fn mark_activity(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    /// Returns the point in time since which the session is idle, meaning the time of the
+    /// last activity.
+    pub fn idle_since(&self) -> Instant {
+        self.last_activity
+    }
+
+    /// Sets the time the client has to answer a request to enable an option (e.g.
+    /// `IAC DO NAWS` of the initial negotiation). Defaults to 10 seconds.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The time after which an unanswered request is treated as refused
+    pub fn set_negotiation_timeout(&mut self, timeout: Duration) {
+        self.negotiation_timeout = timeout;
+    }
+
+    /// Updates the time-dependent state of the session. Requests to enable an option
+    /// that the client didn't answer within the negotiation timeout are treated as
+    /// refused, which is reported as `TelnetEvent::NegotiationTimeout`. Should be
+    /// called periodically, e.g. whenever a read times out.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current point in time
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use telnet_server::telnet::constants::CHAR_NAWS;
+    /// use telnet_server::telnet::{TelnetEvent, TelnetSession};
+    ///
+    /// let mut telnet_session = TelnetSession::builder().request(CHAR_NAWS).build();
+    /// telnet_session.set_negotiation_timeout(Duration::from_secs(5));
+    ///
+    /// telnet_session.tick(Instant::now() + Duration::from_secs(6));
+    /// assert_eq!(
+    ///     telnet_session.take_events(),
+    ///     [TelnetEvent::NegotiationTimeout { option: CHAR_NAWS }]
+    /// );
+    /// ```
+    pub fn tick(&mut self, now: Instant) {
+        let timeout = self.negotiation_timeout;
+        let mut timed_out: Vec<u8> = self
+            .requested_options
+            .iter()
+            .filter(|(_, &requested_at)| now.saturating_duration_since(requested_at) >= timeout)
+            .map(|(&option, _)| option)
+            .collect();
+        timed_out.sort_unstable();
+
+        for option in timed_out {
+            self.requested_options.remove(&option);
+            self.push_event(TelnetEvent::NegotiationTimeout { option });
+        }
+    }
+
+    /// Sets a variable that is reported to MSSP (option 70) crawlers. Setting an
+    /// already existing variable replaces its value. MSSP is only offered to clients
+    /// if at least one variable was set.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the variable, e.g. `NAME` or `PLAYERS`
+    /// * `value` - Value of the variable
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_mssp_var("NAME", "My MUD");
+    /// telnet_session.set_mssp_var("PLAYERS", "42");
+    /// ```
+    pub fn set_mssp_var(&mut self, name: &str, value: &str) {
+        match self.mssp_variables.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self
+                .mssp_variables
+                .push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Registers an observer that is notified synchronously while accepting data. It
+    /// replaces any previously registered observer. Events are still queued, see
+    /// `take_events`.
+    ///
+    /// # Arguments
+    ///
+    /// * `observer` - The observer that should be notified
+    pub fn with_observer(
+        mut self,
+        observer: impl TelnetObserver + Send + Sync + 'static,
+    ) -> TelnetSession {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Records every incoming and outgoing byte to `writer`, which is useful to debug
+    /// negotiations. Every chunk of data is written as a single line, consisting of
+    /// the seconds since the trace started, the direction (`<` for incoming, `>` for
+    /// outgoing) and the bytes with command bytes translated, e.g.
+    /// `0.000012 < IAC DO ECHO "hi"`. Outgoing data is recorded before it's
+    /// compressed. If writing fails, tracing stops. Traces can be replayed via
+    /// `test_util::replay` (feature `test-util`).
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Receives the trace, e.g. a `File`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.set_trace(std::io::stderr());
+    /// telnet_session.accept_data(&[255, 253, 1]); // traced as "< IAC DO ECHO"
+    /// ```
+    pub fn set_trace(&mut self, writer: impl Write + Send + Sync + 'static) {
+        self.trace = Some(Trace {
+            writer: Box::new(writer),
+            start: Instant::now(),
+        });
+    }
+
+    /// Writes given chunk of `data` to the trace, if any
+    ///
+    /// # Arguments
+    ///
+    /// * `direction` - `<` for incoming and `>` for outgoing data
+    /// * `data` - The chunk of data
+    fn record_trace(&mut self, direction: &str, data: &[u8]) {
+        let Some(trace) = self.trace.as_mut() else {
+            return;
+        };
+
+        if data.is_empty() {
+            return;
+        }
+
+        let elapsed = trace.start.elapsed().as_secs_f64();
+        let line = format!("{elapsed:.6} {direction} {}\n", describe_command(data));
+
+        if let Err(e) = trace.writer.write_all(line.as_bytes()) {
+            error!("Writing the trace failed, stopping it: {e}");
+            self.trace = None;
+        }
+    }
+
+    /// Queues given event and notifies the observer, if any
+    fn push_event(&mut self, event: TelnetEvent) {
+        if let TelnetEvent::Negotiation { .. } = event {
+            /* The client negotiates, so it speaks Telnet */
+            self.first_bytes = None;
+        }
+
+        if let Some(observer) = self.observer.as_mut() {
+            match &event {
+                TelnetEvent::Line(line) => observer.on_line(line),
+                &TelnetEvent::Negotiation { command, option } => {
+                    observer.on_option(option, matches!(command, CHAR_WILL | CHAR_DO))
+                }
+                _ => {}
+            }
+        }
+
+        if self.events.len() >= self.buffer_limits.events {
+            error!("Event queue full, discarding {event:?}");
+            return;
+        }
+
+        let timestamp = self.is_timestamping_events.then(Instant::now);
+        self.events.push(TimedEvent { event, timestamp });
+    }
+
+    /// Sets the upper limits of the buffers, see `BufferLimits`. Data that's already
+    /// buffered is kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - The new limits
+    pub fn set_buffer_limits(&mut self, limits: BufferLimits) {
+        self.buffer_limits = limits;
+        self.parser
+            .set_sub_negotiation_limit(limits.sub_negotiation);
+    }
+
+    /// Appends incoming data bytes to the data buffer, discarding what exceeds its
+    /// limit. A line break may exceed it by up to two bytes (`\r\n`), so a full buffer
+    /// still gets its line completed.
+    ///
+    /// # Returns
+    ///
+    /// Whether all `bytes` were stored.
+    fn push_data(&mut self, bytes: &[u8]) -> bool {
+        let limit = match bytes {
+            [b'\r' | b'\n'] => self.buffer_limits.data + 2,
+            _ => self.buffer_limits.data,
+        };
+        let capacity = limit.saturating_sub(self.data_bytes.len());
+        let stored = &bytes[..bytes.len().min(capacity)];
+
+        self.data.extend(stored.iter().map(|&b| b as char));
+        self.data_bytes.extend_from_slice(stored);
+        stored.len() == bytes.len()
+    }
+
+    /// Inserts an incoming byte into the data buffer, unless it's full
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Position of the byte in the data buffer
+    /// * `byte` - The incoming byte
+    ///
+    /// # Returns
+    ///
+    /// Whether `byte` was stored.
+    fn insert_data(&mut self, index: usize, byte: u8) -> bool {
+        if self.data_bytes.len() >= self.buffer_limits.data {
+            return false;
+        }
+
+        self.data.insert(index, byte as char);
+        self.data_bytes.insert(index, byte);
+        true
+    }
+
+    /// Removes the char at given position from the data buffer
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Position of the char in the data buffer
+    fn remove_data(&mut self, index: usize) {
+        self.data.remove(index);
+        self.data_bytes.remove(index);
+    }
+
+    /// Removes the last char from the data buffer
+    fn pop_data(&mut self) {
+        self.data.pop();
+        self.data_bytes.pop();
+    }
+
+    /// Erases the current line from the data buffer, see `erase_current_line`
+    fn erase_data_line(&mut self) {
+        erase_current_line(&mut self.data);
+        erase_current_line(&mut self.data_bytes);
+    }
+
+    /// Returns a builder for a `TelnetSession` with an initial negotiation and banner
+    pub fn builder() -> TelnetSessionBuilder {
+        TelnetSessionBuilder {
+            pass_ansi_escape_sequences: false,
+            requested_options: vec![],
+            banner: vec![],
+            normalize_banner_line_breaks: false,
+        }
+    }
+
+    /// Returns the output that should be sent to the client right after it connected:
+    /// The initial negotiation followed by the banner, as configured by the builder.
+    /// It's only returned once, following calls return nothing.
+    pub fn initial_negotiation(&mut self) -> Vec<u8> {
+        let initial_output = std::mem::take(&mut self.initial_output);
+        self.prepare_output(&initial_output)
+    }
+
+    /// Creates a new `TelnetSettion`
+    ///
+    /// # Arguments
+    /// * `pass_ansi_escape_sequences` - if true, ANSI escape sequences will be
+    ///   handled, returned etc. Otherwise they will be ignored
+    pub fn create(pass_ansi_escape_sequences: bool) -> TelnetSession {
+        #[cfg(not(feature = "ansi"))]
+        let _ = pass_ansi_escape_sequences;
+
+        TelnetSession {
+            data: vec![],
+            data_bytes: vec![],
+            stream: vec![],
+            pending_output: vec![],
+            is_after_carriage_return: false,
+            parser: TelnetParser::new(),
+            state: TelnetState::Idle,
+            is_paused: false,
+            is_echoing: false,
+            echo_mask: None,
+            is_caret_echo: false,
+            is_editing_lines: false,
+            edit_cursor_offset: 0,
+            is_echo_by_line_editing: false,
+            max_line_length: None,
+            tab_stops: None,
+            nul_handling: NulHandling::default(),
+            is_converting_output_line_breaks: false,
+            is_output_after_carriage_return: false,
+            echo_utf8_buffer: vec![],
+            is_end_of_record_active: false,
+            is_suppressing_go_ahead: false,
+            buffer_limits: BufferLimits::default(),
+            linemode_flags: 0,
+            is_environment_requested: false,
+            environment: HashMap::new(),
+            encoding: Encoding::default(),
+            is_charset_detection_enabled: true,
+            is_terminal_type_requested: false,
+            terminal_types: vec![],
+            terminal_capabilities: None,
+            is_sending_terminal_type: false,
+            server_terminal_type: "UNKNOWN".to_string(),
+            is_x_display_location_requested: false,
+            x_display_location: None,
+            client_location: None,
+            is_terminal_speed_requested: false,
+            terminal_speed: None,
+            window_size: None,
+            is_flow_control_on: true,
+            flow_control_restart: None,
+            is_output_paused: false,
+            auth_backend: None,
+            is_authentication_requested: false,
+            is_authenticated: false,
+            #[cfg(feature = "ansi")]
+            is_passing_escape_sequences: pass_ansi_escape_sequences,
+            #[cfg(feature = "ansi")]
+            escape_sequence: vec![],
+            #[cfg(feature = "ansi")]
+            is_bell_on_unknown_escape: false,
+            #[cfg(feature = "ansi")]
+            paste: None,
+            last_activity: Instant::now(),
+            events: vec![],
+            is_timestamping_events: false,
+            error_policy: ErrorPolicy::default(),
+            fatal_error: None,
+            first_bytes: Some(vec![]),
+            mssp_variables: vec![],
+            #[cfg(feature = "mccp")]
+            compression: CompressionState::Disabled,
+            option_policy: None,
+            remote_options: HashSet::new(),
+            sub_negotiation_fallback: None,
+            unknown_command_handler: None,
+            input_filters: vec![],
+            requested_options: HashMap::new(),
+            negotiation_timeout: DEFAULT_NEGOTIATION_TIMEOUT,
+            initial_output: vec![],
+            observer: None,
+            trace: None,
+        }
+    }
+
+    /// Returns a token of the negotiated state of this session (e.g. echo, enabled
+    /// options, environment), see `TelnetSession::resume`
+    pub fn session_token(&self) -> SessionToken {
+        SessionToken {
+            is_echoing: self.is_echoing,
+            is_end_of_record_active: self.is_end_of_record_active,
+            is_suppressing_go_ahead: self.is_suppressing_go_ahead,
+            linemode_flags: self.linemode_flags,
+            remote_options: self.remote_options.clone(),
+            environment: self.environment.clone(),
+            encoding: self.encoding,
+            terminal_types: self.terminal_types.clone(),
+            terminal_capabilities: self.terminal_capabilities,
+            x_display_location: self.x_display_location.clone(),
+            client_location: self.client_location.clone(),
+            terminal_speed: self.terminal_speed,
+            window_size: self.window_size,
+            #[cfg(feature = "ansi")]
+            is_passing_escape_sequences: self.is_passing_escape_sequences,
+        }
+    }
+
+    /// Creates a new `TelnetSession` with the negotiated state of given `token`, e.g.
+    /// for a client that reconnected. Nothing is negotiated again and the data buffer
+    /// starts empty. MCCP2 compression has to be offered again, as the compressed
+    /// stream can't be resumed.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The token of the previous session, see `TelnetSession::session_token`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::constants::{CHAR_DO, CHAR_ECHO, CHAR_IAC};
+    /// use telnet_server::telnet::TelnetSession;
+    ///
+    /// let mut telnet_session = TelnetSession::create(false);
+    /// telnet_session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+    ///
+    /// let resumed = TelnetSession::resume(telnet_session.session_token());
+    /// assert!(resumed.is_echoing());
+    /// ```
+    pub fn resume(token: SessionToken) -> TelnetSession {
+        #[cfg(feature = "ansi")]
+        let mut session = TelnetSession::create(token.is_passing_escape_sequences);
+        #[cfg(not(feature = "ansi"))]
+        let mut session = TelnetSession::create(false);
+
+        session.is_echoing = token.is_echoing;
+        session.is_end_of_record_active = token.is_end_of_record_active;
+        session.is_suppressing_go_ahead = token.is_suppressing_go_ahead;
+        session.linemode_flags = token.linemode_flags;
+        session.remote_options = token.remote_options;
+        session.environment = token.environment;
+        session.encoding = token.encoding;
+        session.terminal_types = token.terminal_types;
+        session.terminal_capabilities = token.terminal_capabilities;
+        session.x_display_location = token.x_display_location;
+        session.client_location = token.client_location;
+        session.terminal_speed = token.terminal_speed;
+        session.window_size = token.window_size;
+        /* The client already spoke Telnet before */
+        session.first_bytes = None;
+        session
+    }
+}
+
+/// Opaque token of the negotiated state of a `TelnetSession`, see
+/// `TelnetSession::session_token` and `TelnetSession::resume`.
+#[derive(Debug, Clone)]
+pub struct SessionToken {
+    is_echoing: bool,
+    is_end_of_record_active: bool,
+    is_suppressing_go_ahead: bool,
+    linemode_flags: u8,
+    remote_options: HashSet<u8>,
+    environment: HashMap<String, String>,
+    encoding: Encoding,
+    terminal_types: Vec<String>,
+    terminal_capabilities: Option<TerminalCapabilities>,
+    x_display_location: Option<String>,
+    client_location: Option<String>,
+    terminal_speed: Option<(u32, u32)>,
+    window_size: Option<(u16, u16)>,
+    #[cfg(feature = "ansi")]
+    is_passing_escape_sequences: bool,
+}
+
+/// Builder of a `TelnetSession`, see `TelnetSession::builder`.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::constants::CHAR_NAWS;
+/// use telnet_server::telnet::TelnetSession;
+///
+/// let mut telnet_session = TelnetSession::builder()
+///     .request(CHAR_NAWS)
+///     .banner("Welcome!\n")
+///     .normalize_banner_line_breaks(true)
+///     .build();
+///
+/// assert_eq!(
+///     telnet_session.initial_negotiation(),
+///     b"\xFF\xFD\x1FWelcome!\r\n"
+/// );
+/// ```
+pub struct TelnetSessionBuilder {
+    /// Whether ANSI escape sequences should be passed on, see `TelnetSession::create`
+    pass_ansi_escape_sequences: bool,
+    /// Options the client is asked to enable (`IAC DO option`) on connect
+    requested_options: Vec<u8>,
+    /// Banner that's sent after the initial negotiation, unescaped
+    banner: Vec<u8>,
+    /// Whether line breaks of the banner are normalized to CRLF
+    normalize_banner_line_breaks: bool,
+}
+
+impl TelnetSessionBuilder {
+    /// Sets whether ANSI escape sequences should be passed on, see
+    /// `TelnetSession::create`. Defaults to false.
+    pub fn pass_ansi_escape_sequences(mut self, pass: bool) -> TelnetSessionBuilder {
+        self.pass_ansi_escape_sequences = pass;
+        self
+    }
+
+    /// Asks the client to enable given option (`IAC DO option`) during the initial
+    /// negotiation. If the client agrees, the option is enabled without further reply.
+    ///
+    /// # Arguments
+    ///
+    /// * `option` - The option, e.g. `CHAR_NAWS`
+    pub fn request(mut self, option: u8) -> TelnetSessionBuilder {
+        self.requested_options.push(option);
+        self
+    }
+
+    /// Sets a banner (e.g. a welcome message) that's sent after the initial
+    /// negotiation. `IAC` bytes are escaped.
+    ///
+    /// # Arguments
+    ///
+    /// * `banner` - The banner text or bytes
+    pub fn banner(mut self, banner: impl AsRef<[u8]>) -> TelnetSessionBuilder {
+        self.banner = banner.as_ref().to_vec();
+        self
+    }
+
+    /// Sets whether bare `\n` line breaks of the banner are replaced by CRLF, as
+    /// required by the network virtual terminal. Defaults to false.
+    pub fn normalize_banner_line_breaks(mut self, normalize: bool) -> TelnetSessionBuilder {
+        self.normalize_banner_line_breaks = normalize;
+        self
+    }
+
+    /// Builds the configured `TelnetSession`
+    pub fn build(self) -> TelnetSession {
+        let mut initial_output = vec![];
+
+        for &option in &self.requested_options {
+            initial_output.extend_from_slice(&iac_command(CHAR_DO, option));
+        }
+
+        let mut previous = None;
+        for &byte in &self.banner {
+            if self.normalize_banner_line_breaks && byte == b'\n' && previous != Some(b'\r') {
+                initial_output.push(b'\r');
+            }
+
+            initial_output.push(byte);
+            if byte == CHAR_IAC {
+                initial_output.push(CHAR_IAC);
+            }
+
+            previous = Some(byte);
+        }
+
+        let mut session = TelnetSession::create(self.pass_ansi_escape_sequences);
+        session.initial_output = initial_output;
+
+        for option in self.requested_options {
+            session.mark_requested(option);
+        }
+
+        session
+    }
+}
+
+/// Builds a negotiation command sequence (`IAC verb option`)
+///
+/// # Arguments
+///
+/// * `verb` - The negotiation command, e.g. `CHAR_WILL`
+/// * `option` - The negotiated option, e.g. `CHAR_ECHO`
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::constants::{CHAR_ECHO, CHAR_WILL};
+/// use telnet_server::telnet::iac_command;
+///
+/// assert_eq!(iac_command(CHAR_WILL, CHAR_ECHO), [255, 251, 1]);
+/// ```
+pub fn iac_command(verb: u8, option: u8) -> [u8; 3] {
+    [CHAR_IAC, verb, option]
+}
+
+/// Builds a sub negotiation sequence (`IAC SB option payload IAC SE`). Every `IAC` in
+/// `payload` is escaped by doubling it.
+///
+/// # Arguments
+///
+/// * `option` - The negotiated option
+/// * `payload` - The unescaped sub negotiation parameters
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::iac_subneg;
+///
+/// assert_eq!(iac_subneg(24, &[1]), [255, 250, 24, 1, 255, 240]);
+/// assert_eq!(iac_subneg(99, &[255]), [255, 250, 99, 255, 255, 255, 240]);
+/// ```
+pub fn iac_subneg(option: u8, payload: &[u8]) -> Vec<u8> {
+    let mut sub_negotiation = vec![CHAR_IAC, CHAR_SUB_NEGOTIATION, option];
+
+    for &byte in payload {
+        sub_negotiation.push(byte);
+
+        if byte == CHAR_IAC {
+            sub_negotiation.push(CHAR_IAC);
+        }
+    }
+
+    sub_negotiation.extend_from_slice(&[CHAR_IAC, CHAR_SUB_NEGOTIATION_END]);
+    sub_negotiation
+}
+
+/// Converts lone `\n` line breaks of outgoing `data` to `\r\n`. Command sequences
+/// are copied unchanged, they're expected to be complete within `data`.
+///
+/// # Arguments
+///
+/// * `data` - The outgoing data
+/// * `is_after_carriage_return` - Whether the byte before `data` was a `\r`, updated
+///   to the last data byte of `data`
+fn convert_output_line_breaks(data: &[u8], is_after_carriage_return: &mut bool) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().copied();
+
+    while let Some(byte) = bytes.next() {
+        if byte != CHAR_IAC {
+            if byte == b'\n' && !*is_after_carriage_return {
+                converted.push(b'\r');
+            }
+
+            *is_after_carriage_return = byte == b'\r';
+            converted.push(byte);
+            continue;
+        }
+
+        /* Copy the command sequence, which may contain any byte */
+        converted.push(byte);
+        match bytes.next() {
+            Some(command @ (CHAR_WILL | CHAR_WONT | CHAR_DO | CHAR_DONT)) => {
+                converted.push(command);
+                converted.extend(bytes.next());
+            }
+            Some(CHAR_SUB_NEGOTIATION) => {
+                converted.push(CHAR_SUB_NEGOTIATION);
+
+                let mut previous = None;
+                for byte in bytes.by_ref() {
+                    converted.push(byte);
+
+                    if previous == Some(CHAR_IAC) && byte == CHAR_SUB_NEGOTIATION_END {
+                        break;
+                    }
+
+                    /* IAC IAC is an escaped 255 and doesn't start IAC SE */
+                    previous = match previous {
+                        Some(CHAR_IAC) => None,
+                        _ => Some(byte),
+                    };
+                }
+            }
+            Some(command) => converted.push(command),
+            None => {}
+        }
+    }
+
+    converted
+}
+
+/// Splits given `data` into text and command segments at `IAC` boundaries, without a
+/// stateful `TelnetSession`. `IAC IAC` is an escaped data byte and part of the text.
+/// An incomplete command at the end of `data` is returned as `TelnetSegment::Partial`,
+/// so it can be prepended to the next data. The data is tokenized by a `TelnetParser`
+/// like within a session: Any command but `IAC SE` and `IAC IAC` aborts a sub
+/// negotiation and an `IAC` instead of an option aborts a negotiation. The aborted
+/// command is returned as a command segment on its own.
+///
+/// # Arguments
+///
+/// * `data` - The data, e.g. a chunk of incoming data
+///
+/// # Returns
+///
+/// The segments in order of `data`.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::{split_telnet, TelnetSegment};
+///
+/// assert_eq!(
+///     split_telnet(&[b'h', b'i', 255, 251, 1, 255, 255, 255]),
+///     [
+///         TelnetSegment::Text(b"hi".to_vec()),
+///         TelnetSegment::Command(vec![255, 251, 1]),
+///         TelnetSegment::Text(vec![255]),
+///         TelnetSegment::Partial(vec![255]),
+///     ]
+/// );
+/// ```
+pub fn split_telnet(data: &[u8]) -> Vec<TelnetSegment> {
+    let mut parser = TelnetParser::new();
+    let mut segments = vec![];
+    let mut text = vec![];
+    /* Index of the first byte of the current command */
+    let mut start = 0;
+
+    parser.set_sub_negotiation_limit(usize::MAX);
+
+    for (index, &byte) in data.iter().enumerate() {
+        if parser.state() == ParserState::Idle {
+            start = index;
+        }
+
+        let token = parser.update(byte);
+
+        /* The erroneous command ends before the `IAC` that interrupted it */
+        let interrupt = match parser.take_error() {
+            Some(ProtocolError::IncompleteNegotiation) => Some(index),
+            Some(ProtocolError::AbortedSubNegotiation { .. }) => Some(index - 1),
+            _ => None,
+        };
+
+        if let Some(interrupt) = interrupt {
+            let command = data[start..interrupt].to_vec();
+            push_segment(&mut segments, &mut text, TelnetSegment::Command(command));
+            start = interrupt;
+        }
+
+        match token {
+            Some(TelnetToken::Data(byte)) => text.push(byte),
+            Some(_) => {
+                let command = data[start..=index].to_vec();
+                push_segment(&mut segments, &mut text, TelnetSegment::Command(command));
+            }
+            None => {}
+        }
+    }
+
+    if parser.state() != ParserState::Idle {
+        let partial = data[start..].to_vec();
+        push_segment(&mut segments, &mut text, TelnetSegment::Partial(partial));
+    } else if !text.is_empty() {
+        segments.push(TelnetSegment::Text(text));
+    }
+
+    segments
+}
+
+/// Appends given `segment` to `segments`, preceded by the collected `text`, if any
+///
+/// # Arguments
+///
+/// * `segments` - The segments so far
+/// * `text` - The text that was collected before `segment`, which is emptied
+/// * `segment` - The command or partial segment
+fn push_segment(segments: &mut Vec<TelnetSegment>, text: &mut Vec<u8>, segment: TelnetSegment) {
+    if !text.is_empty() {
+        segments.push(TelnetSegment::Text(std::mem::take(text)));
+    }
+
+    segments.push(segment);
+}
+
+/// Renders a sequence of Telnet bytes human-readably, e.g. for logs or admin tooling:
+/// Commands and options are replaced by their names (e.g. `IAC WILL ECHO`), unknown
+/// ones by their number, and data is quoted. Within sub negotiations, printable
+/// parameters are quoted as well and other parameters are numbers.
+///
+/// # Arguments
+///
+/// * `bytes` - The bytes, e.g. a command sequence or a chunk of incoming data
+///
+/// # Returns
+///
+/// The names and quoted data, separated by spaces.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::describe_command;
+///
+/// assert_eq!(describe_command(&[255, 251, 1]), "IAC WILL ECHO");
+/// assert_eq!(
+///     describe_command(&[255, 250, 24, 0, b'V', b'T', 255, 240]),
+///     "IAC SB TERMINAL-TYPE 0 \"VT\" IAC SE"
+/// );
+/// ```
+pub fn describe_command(bytes: &[u8]) -> String {
+    /// Enumeration of positions within a Telnet sequence
+    enum Position {
+        Data,
+        Command,
+        Option { is_sub_negotiation: bool },
+        SubNegotiation,
+        SubNegotiationIac,
+    }
+
+    let mut tokens: Vec<String> = vec![];
+    let mut text: Vec<u8> = vec![];
+    let mut position = Position::Data;
+
+    let flush_text = |tokens: &mut Vec<String>, text: &mut Vec<u8>| {
+        if !text.is_empty() {
+            tokens.push(format!("\"{}\"", text.escape_ascii()));
+            text.clear();
+        }
+    };
+
+    for &byte in bytes {
+        position = match position {
+            Position::Data if byte == CHAR_IAC => {
+                flush_text(&mut tokens, &mut text);
+                tokens.push("IAC".to_string());
+                Position::Command
+            }
+            Position::Data => {
+                text.push(byte);
+                Position::Data
+            }
+            Position::SubNegotiationIac if byte == CHAR_SUB_NEGOTIATION_END => {
+                tokens.push("SE".to_string());
+                Position::Data
+            }
+            Position::SubNegotiationIac if byte == CHAR_IAC => {
+                tokens.push("IAC".to_string());
+                Position::SubNegotiation
+            }
+            /* Any other command aborts the sub negotiation, see `TelnetParser` */
+            Position::Command | Position::SubNegotiationIac => {
+                tokens.push(describe_byte(byte, command_name(byte)));
+                match byte {
+                    CHAR_WILL | CHAR_WONT | CHAR_DO | CHAR_DONT => Position::Option {
+                        is_sub_negotiation: false,
+                    },
+                    CHAR_SUB_NEGOTIATION => Position::Option {
+                        is_sub_negotiation: true,
+                    },
+                    _ => Position::Data,
+                }
+            }
+            Position::Option { is_sub_negotiation } => {
+                tokens.push(describe_byte(byte, option_name(byte)));
+                if is_sub_negotiation {
+                    Position::SubNegotiation
+                } else {
+                    Position::Data
+                }
+            }
+            Position::SubNegotiation if byte == CHAR_IAC => {
+                flush_text(&mut tokens, &mut text);
+                tokens.push("IAC".to_string());
+                Position::SubNegotiationIac
+            }
+            Position::SubNegotiation if byte.is_ascii_graphic() || byte == b' ' => {
+                text.push(byte);
+                Position::SubNegotiation
+            }
+            Position::SubNegotiation => {
+                flush_text(&mut tokens, &mut text);
+                tokens.push(byte.to_string());
+                Position::SubNegotiation
+            }
+        };
+    }
+
+    flush_text(&mut tokens, &mut text);
+    tokens.join(" ")
+}
+
+/// Parses a rendering of `describe_command` back into the Telnet bytes, e.g. to replay
+/// a trace (see `TelnetSession::set_trace`)
+///
+/// # Arguments
+///
+/// * `description` - The names, numbers and quoted data, separated by spaces
+///
+/// # Returns
+///
+/// The described bytes or `None` if `description` is malformed.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::parse_description;
+///
+/// let bytes = parse_description(r#"IAC WILL ECHO "hi\r""#);
+/// assert_eq!(bytes, Some(b"\xFF\xFB\x01hi\r".to_vec()));
+/// assert_eq!(parse_description("IAC WILL NONSENSE"), None);
+/// ```
+pub fn parse_description(description: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut rest = description.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let (text, remainder) = parse_quoted_text(quoted)?;
+            bytes.extend(text);
+            rest = remainder;
+        } else {
+            let (token, remainder) = rest.split_once(' ').unwrap_or((rest, ""));
+            let byte = token.parse::<u8>().ok().or_else(|| {
+                /* Names of commands and options don't overlap */
+                (0..=u8::MAX).find(|&byte| {
+                    command_name(byte) == Some(token) || option_name(byte) == Some(token)
+                })
+            })?;
+            bytes.push(byte);
+            rest = remainder;
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Some(bytes)
+}
+
+/// Parses quoted data of `describe_command`, which is escaped via `escape_ascii`
+///
+/// # Arguments
+///
+/// * `quoted` - The quoted data, starting after the opening quote
+///
+/// # Returns
+///
+/// The unescaped data and the rest after the closing quote, or `None` if the data is
+/// malformed.
+fn parse_quoted_text(quoted: &str) -> Option<(Vec<u8>, &str)> {
+    let mut text = vec![];
+    let mut chars = quoted.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        let byte = match c {
+            '"' => return Some((text, &quoted[index + 1..])),
+            '\\' => match chars.next()?.1 {
+                't' => b'\t',
+                'r' => b'\r',
+                'n' => b'\n',
+                'x' => {
+                    let high = chars.next()?.1.to_digit(16)?;
+                    let low = chars.next()?.1.to_digit(16)?;
+                    (high * 16 + low) as u8
+                }
+                escaped @ ('\'' | '"' | '\\') => escaped as u8,
+                _ => return None,
+            },
+            _ if c.is_ascii() => c as u8,
+            _ => return None,
+        };
+
+        text.push(byte);
+    }
+
+    /* The closing quote is missing */
+    None
+}
+
+/// Returns the `name` of a byte or its number, if it has no name
+fn describe_byte(byte: u8, name: Option<&str>) -> String {
+    name.map_or_else(|| byte.to_string(), str::to_string)
+}
+
+/// Returns the name of given command byte (following `IAC`), if it's known
+fn command_name(command: u8) -> Option<&'static str> {
+    let name = match command {
+        CHAR_EOR => "EOR",
+        CHAR_SUB_NEGOTIATION_END => "SE",
+        CHAR_NOP => "NOP",
+        CHAR_DATA_MARK => "DM",
+        CHAR_BREAK => "BRK",
+        CHAR_ERASE_CHARACTER => "EC",
+        CHAR_ERASE_LINE => "EL",
+        CHAR_GO_AHEAD => "GA",
+        CHAR_SUB_NEGOTIATION => "SB",
+        CHAR_WILL => "WILL",
+        CHAR_WONT => "WONT",
+        CHAR_DO => "DO",
+        CHAR_DONT => "DONT",
+        CHAR_IAC => "IAC",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// Returns the name of given option, if it's known
+fn option_name(option: u8) -> Option<&'static str> {
+    let name = match option {
+        CHAR_ECHO => "ECHO",
+        CHAR_SUPPRESS_GO_AHEAD => "SUPPRESS-GO-AHEAD",
+        CHAR_TIMING_MARK => "TIMING-MARK",
+        CHAR_SEND_LOCATION => "SEND-LOCATION",
+        CHAR_TERMINAL_TYPE => "TERMINAL-TYPE",
+        CHAR_END_OF_RECORD => "END-OF-RECORD",
+        CHAR_NAWS => "NAWS",
+        CHAR_TERMINAL_SPEED => "TERMINAL-SPEED",
+        CHAR_TOGGLE_FLOW_CONTROL => "TOGGLE-FLOW-CONTROL",
+        CHAR_LINEMODE => "LINEMODE",
+        CHAR_X_DISPLAY_LOCATION => "X-DISPLAY-LOCATION",
+        CHAR_AUTHENTICATION => "AUTHENTICATION",
+        CHAR_NEW_ENVIRON => "NEW-ENVIRON",
+        CHAR_MSSP => "MSSP",
+        CHAR_MCCP2 => "MCCP2",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// Updates given `session` in `TelnetState::Idle` based on `next` incoming byte
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `next` - The next incoming byte
+///
+/// # Returns
+///
+/// The next state of the session and, if `Some(Vec<u8>)`, the response that should be
+/// sent to the Telnet client.
+fn update_session_idle(session: &mut TelnetSession, next: u8) -> Transition {
+    let is_after_carriage_return = std::mem::take(&mut session.is_after_carriage_return);
+
+    if session.is_output_paused
+        && next != CHAR_XOFF
+        && session.flow_control_restart == Some(FlowControlRestart::Any)
+    {
+        session.is_output_paused = false;
+    }
+
+    if session.is_editing_lines {
+        if let Some(echo) = edit_line(session, next) {
+            return (TelnetState::Idle, Some(echo).filter(|e| !e.is_empty()));
+        }
+    }
+
+    match next {
+        #[cfg(feature = "ansi")]
+        CHAR_ESCAPE => {
+            session.escape_sequence.clear();
+            session.escape_sequence.push(next);
+            return (TelnetState::AnsiEscapeSequence, None);
+        }
+        #[cfg(feature = "ansi")]
+        _ if session.paste.is_some() => {
+            /* Pasted text is not interpreted, e.g. line breaks don't complete lines. */
+            if let Some(paste) = session.paste.as_mut() {
+                if paste.len() >= session.buffer_limits.data {
+                    /* Dropped bytes aren't echoed */
+                    return (TelnetState::Idle, None);
+                }
+
+                paste.push(next);
+            }
+
+            return (TelnetState::Idle, echo_data(session, next));
+        }
+        CHAR_XOFF if session.is_flow_control_enabled() => session.is_output_paused = true,
+        CHAR_XON if session.is_flow_control_enabled() => session.is_output_paused = false,
+        CHAR_NUL if is_after_carriage_return && session.nul_handling == NulHandling::LineFeed => {
+            /* The \r already completed the line, so the \n only follows it. */
+            session.is_after_carriage_return = true;
+            return update_session_idle(session, b'\n');
+        }
+        CHAR_NUL if session.nul_handling != NulHandling::Keep => {}
+        CHAR_DELETE | CHAR_BACK_SPACE | CHAR_ERASE_CHARACTER => {
+            session.pop_data();
+
+            if session.is_echoing {
+                /* Return fake backspace on echo mode */
+                return (
+                    TelnetState::Idle,
+                    Some(vec![CHAR_BACK_SPACE, b' ', CHAR_BACK_SPACE]),
+                );
+            }
+        }
+        CHAR_ERASE_LINE => session.erase_data_line(),
+        _ if (next >= b' ' || next == CHAR_TAB)
+            && session
+                .max_line_length
+                .is_some_and(|max| session.current_line_length() >= max) =>
+        {
+            /* The line is too long, reject the char. */
+            if session.is_echoing {
+                return (TelnetState::Idle, Some(vec![CHAR_BEL]));
+            }
+        }
+        CHAR_TAB if session.tab_stops.is_some_and(|width| width > 0) => {
+            let width = session.tab_stops.unwrap_or_default();
+            let column = session.current_line_length();
+            let room = session
+                .max_line_length
+                .map_or(usize::MAX, |max| max - column);
+            let mut echo = vec![];
+
+            /* Expanded tabs don't exceed the maximum line length either */
+            for _ in 0..(width - column % width).min(room) {
+                if !session.push_data(b" ") {
+                    break;
+                }
+
+                echo.extend(echo_data(session, b' ').unwrap_or_default());
+            }
+
+            if !echo.is_empty() {
+                return (TelnetState::Idle, Some(echo));
+            }
+        }
+        _ => {
+            /* A bare \r (e.g. from old Mac clients) terminates a line as well. The \n
+             * of a \r\n was already handled by its \r. */
+            let is_line_complete = next == b'\r' || (next == b'\n' && !is_after_carriage_return);
+
+            if is_line_complete && !session.input_filters.is_empty() {
+                session.filter_current_line();
+            }
+
+            /* Unless the \r of this \r\n was already taken from the buffer, so the \n
+             * would start an empty line. */
+            let is_pushed =
+                next != b'\n' || !is_after_carriage_return || session.data_bytes.ends_with(b"\r");
+
+            if is_pushed && !session.push_data(&[next]) {
+                /* The data buffer is full, dropped bytes aren't echoed */
+                return (TelnetState::Idle, None);
+            }
+
+            if is_line_complete {
+                let line = current_line(&session.data_bytes, session.encoding);
+                session.push_event(TelnetEvent::Line(line));
+            }
+
+            session.is_after_carriage_return = next == b'\r';
+
+            return (TelnetState::Idle, echo_data(session, next));
+        }
+    }
+
+    (TelnetState::Idle, None)
+}
+
+/// Edits the current line based on `next` incoming byte, while lines are edited
+/// server-side. Bytes that don't edit the line in a special way (e.g. chars appended
+/// at its end or line breaks) aren't handled.
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `next` - The next incoming byte
+///
+/// # Returns
+///
+/// If `Some(Vec<u8>)` is returned, the byte was handled and the echo should be sent to
+/// the Telnet client.
+fn edit_line(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
+    #[cfg(feature = "ansi")]
+    if session.paste.is_some() {
+        return None;
+    }
+
+    let line_length = session.current_line_length();
+    let offset = session.edit_cursor_offset.min(line_length);
+    let cursor = session.data_bytes.len() - offset;
+    let column = line_length - offset;
+
+    let mut echo = vec![];
+
+    match next {
+        CHAR_DELETE | CHAR_BACK_SPACE | CHAR_ERASE_CHARACTER => {
+            if column > 0 {
+                session.remove_data(cursor - 1);
+
+                /* Redraw the rest of the line, one column to the left */
+                echo.push(CHAR_BACK_SPACE);
+                echo.extend(echo_line_rest(session, offset));
+                echo.push(b' ');
+                echo.extend(std::iter::repeat_n(CHAR_BACK_SPACE, offset + 1));
+            }
+        }
+        CHAR_NAK | CHAR_ERASE_LINE => {
+            let erased = session.data_bytes.len() - line_length;
+            session.data.truncate(erased);
+            session.data_bytes.truncate(erased);
+            session.edit_cursor_offset = 0;
+
+            echo.extend(std::iter::repeat_n(CHAR_BACK_SPACE, column));
+            echo.extend(std::iter::repeat_n(b' ', line_length));
+            echo.extend(std::iter::repeat_n(CHAR_BACK_SPACE, line_length));
+        }
+        b'\r' | b'\n' => {
+            session.edit_cursor_offset = 0;
+            return None;
+        }
+        _ if next >= b' ' && offset > 0 => {
+            if session
+                .max_line_length
+                .is_some_and(|max| line_length >= max)
+            {
+                echo.push(CHAR_BEL);
+            } else if session.insert_data(cursor, next) {
+                /* Redraw the rest of the line, one column to the right */
+                echo.extend(echo_char(session, &[next]));
+                echo.extend(echo_line_rest(session, offset));
+                echo.extend(std::iter::repeat_n(CHAR_BACK_SPACE, offset));
+            }
+        }
+        _ => return None,
+    }
+
+    if !session.is_echoing {
+        echo.clear();
+    }
+
+    Some(echo)
+}
+
+/// Moves the cursor within the current line based on given escape sequence (arrow
+/// keys, Home and End), while lines are edited server-side
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `escape_sequence` - The complete escape sequence
+///
+/// # Returns
+///
+/// If `Some(Vec<u8>)` is returned, the cursor was moved and the echo should be sent to
+/// the Telnet client.
+#[cfg(feature = "ansi")]
+fn move_edit_cursor(session: &mut TelnetSession, escape_sequence: &[u8]) -> Option<Vec<u8>> {
+    let line_length = session.current_line_length();
+    let offset = session.edit_cursor_offset.min(line_length);
+
+    let new_offset = match escape_sequence {
+        [CHAR_ESCAPE, b'[', b'D'] => (offset + 1).min(line_length),
+        [CHAR_ESCAPE, b'[', b'C'] => offset.saturating_sub(1),
+        [CHAR_ESCAPE, b'[', b'H'] => line_length,
+        [CHAR_ESCAPE, b'[', b'F'] => 0,
+        _ => return None,
+    };
+    session.edit_cursor_offset = new_offset;
+
+    let echo = if new_offset > offset {
+        vec![CHAR_BACK_SPACE; new_offset - offset]
+    } else {
+        /* Moving right, the chars in between are written again */
+        let moved = session.data_bytes.len() - offset..session.data_bytes.len() - new_offset;
+        session.data_bytes[moved]
+            .iter()
+            .flat_map(|&byte| echo_char(session, &[byte]))
+            .collect()
+    };
+
+    if !session.is_echoing {
+        return Some(vec![]);
+    }
+
+    Some(echo)
+}
+
+/// Returns the echo of the current line right of the cursor
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `offset` - Number of bytes right of the cursor
+fn echo_line_rest(session: &TelnetSession, offset: usize) -> Vec<u8> {
+    session.data_bytes[session.data_bytes.len() - offset..]
+        .iter()
+        .flat_map(|&byte| echo_char(session, &[byte]))
+        .collect()
+}
+
+/// Handles a command of the client (e.g. `IAC WILL ECHO` or `IAC NOP`)
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `command` - The command
+/// * `option` - The option, if `command` is a negotiation
+///
+/// # Returns
+///
+/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
+fn handle_command(session: &mut TelnetSession, command: u8, option: Option<u8>) -> Option<Vec<u8>> {
+    match (command, option) {
+        (CHAR_WILL, Some(option)) => negotiate_will(session, option),
+        (CHAR_WONT, Some(option)) => negotiate_wont(session, option),
+        (CHAR_DO, Some(option)) => negotiate_do(session, option),
+        (CHAR_DONT, Some(option)) => negotiate_dont(session, option),
+        (CHAR_DATA_MARK, _) => {
+            /* RFC-854: The Synch discards all data up to the data mark. The TCP urgent
+             * notification can't be detected reliably via std's TcpStream, so only the
+             * in-band mark is handled (best-effort). */
+            session.clear_data_buffer();
+            session.push_event(TelnetEvent::Synch);
+            None
+        }
+        (CHAR_BREAK, _) => {
+            /* Like a serial break, so discard the partial input line */
+            session.erase_data_line();
+            session.push_event(TelnetEvent::Break);
+            None
+        }
+        (CHAR_NOP, _) => None,
+        _ => match session.unknown_command_handler.as_mut() {
+            Some(handler) => handler(command),
+            None => {
+                error!("Not implemented command: {command}");
+                None
+            }
+        },
+    }
+}
+
+/// Answers the negotiation `IAC WILL next` of the client
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `next` - The negotiated option
+///
+/// # Returns
+///
+/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
+fn negotiate_will(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
+    session.push_event(TelnetEvent::Negotiation {
+        command: CHAR_WILL,
+        option: next,
+    });
+
+    if session.is_option_refused(next, Negotiation::Will) {
+        session.remote_options.remove(&next);
+        return Some(vec![CHAR_IAC, CHAR_DONT, next]);
+    }
+
+    if next == CHAR_ECHO && session.is_echoing {
+        /* RFC-857: If both sides echoed, every char would be sent back and forth
+         * endlessly. */
+        session.requested_options.remove(&next);
+        return Some(vec![CHAR_IAC, CHAR_DONT, CHAR_ECHO]);
+    }
+
+    if next == CHAR_NEW_ENVIRON {
+        session.requested_options.remove(&next);
+        /* Client is willing to send its environment, so request all variables. */
+        session.remote_options.insert(next);
+        return Some(build_send_request(
+            &mut session.is_environment_requested,
+            CHAR_NEW_ENVIRON,
+            CHAR_ENVIRON_SEND,
+        ));
+    }
+
+    if next == CHAR_TERMINAL_TYPE {
+        session.requested_options.remove(&next);
+        /* Client is willing to send its terminal type, so start the cycle. */
+        session.remote_options.insert(next);
+        session.terminal_types.clear();
+        return Some(build_send_request(
+            &mut session.is_terminal_type_requested,
+            CHAR_TERMINAL_TYPE,
+            CHAR_TERMINAL_TYPE_SEND,
+        ));
+    }
+
+    if next == CHAR_X_DISPLAY_LOCATION {
+        session.requested_options.remove(&next);
+        /* Client is willing to send its X display location, so request it. */
+        session.remote_options.insert(next);
+        return Some(build_send_request(
+            &mut session.is_x_display_location_requested,
+            CHAR_X_DISPLAY_LOCATION,
+            CHAR_X_DISPLAY_LOCATION_SEND,
+        ));
+    }
+
+    if next == CHAR_AUTHENTICATION {
+        if let Some(backend) = session.auth_backend.as_ref() {
+            /* Client is willing to authenticate, so send the supported types. */
+            let mut response = vec![];
+            if !std::mem::replace(&mut session.is_authentication_requested, true) {
+                response.extend_from_slice(&iac_command(CHAR_DO, CHAR_AUTHENTICATION));
+            }
+
+            let mut payload = vec![CHAR_AUTHENTICATION_SEND];
+            for (auth_type, modifier) in backend.auth_types() {
+                payload.extend_from_slice(&[auth_type, modifier]);
+            }
+
+            session.remote_options.insert(next);
+            response.extend_from_slice(&iac_subneg(CHAR_AUTHENTICATION, &payload));
+            return Some(response);
+        }
+    }
+
+    if next == CHAR_TERMINAL_SPEED {
+        session.requested_options.remove(&next);
+        /* Client is willing to send its terminal speed, so request it. */
+        session.remote_options.insert(next);
+        return Some(build_send_request(
+            &mut session.is_terminal_speed_requested,
+            CHAR_TERMINAL_SPEED,
+            CHAR_TERMINAL_SPEED_SEND,
+        ));
+    }
+
+    if session.remote_options.contains(&next) {
+        /* Already enabled, replying again would start a negotiation loop. */
+        return None;
+    }
+
+    if session.requested_options.remove(&next).is_some() {
+        /* The client agreed to our request, which needs no reply. */
+        session.remote_options.insert(next);
+        return None;
+    }
+
+    if next == CHAR_TOGGLE_FLOW_CONTROL {
+        /* The client supports remote flow control, which the session tracks. */
+        session.remote_options.insert(next);
+        return Some(vec![CHAR_IAC, CHAR_DO, next]);
+    }
+
+    if next == CHAR_SEND_LOCATION {
+        /* RFC-779: The client sends its location once we agree. */
+        session.remote_options.insert(next);
+        return Some(vec![CHAR_IAC, CHAR_DO, next]);
+    }
+
+    if session.option_policy.is_some() {
+        /* The policy approved it, so the application wants this option. */
+        session.remote_options.insert(next);
+        return Some(vec![CHAR_IAC, CHAR_DO, next]);
+    }
+
+    /* We don't want whatever they're offering. */
+    Some(vec![CHAR_IAC, CHAR_DONT, next])
+}
+
+/// Answers the negotiation `IAC WONT next` of the client
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `next` - The negotiated option
+///
+/// # Returns
+///
+/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
+fn negotiate_wont(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
+    session.push_event(TelnetEvent::Negotiation {
+        command: CHAR_WONT,
+        option: next,
+    });
+
+    session.requested_options.remove(&next);
+
+    /* The client won't send any data of this option anymore, so forget the old one. */
+    match next {
+        CHAR_NAWS => session.window_size = None,
+        CHAR_TERMINAL_TYPE => {
+            session.terminal_types.clear();
+            session.terminal_capabilities = None;
+        }
+        CHAR_X_DISPLAY_LOCATION => session.x_display_location = None,
+        CHAR_SEND_LOCATION => session.client_location = None,
+        CHAR_TERMINAL_SPEED => session.terminal_speed = None,
+        CHAR_TOGGLE_FLOW_CONTROL => {
+            session.is_flow_control_on = true;
+            session.flow_control_restart = None;
+            session.is_output_paused = false;
+        }
+        CHAR_AUTHENTICATION => session.is_authenticated = false,
+        _ => {}
+    }
+
+    /* Acknowledge that the client disabled an option. Options that weren't enabled
+     * are ignored, as replying would start a negotiation loop. */
+    if session.remote_options.remove(&next) {
+        return Some(vec![CHAR_IAC, CHAR_DONT, next]);
+    }
+
+    None
+}
+
+/// Answers the negotiation `IAC DO next` of the client
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `next` - The negotiated option
+///
+/// # Returns
+///
+/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
+fn negotiate_do(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
+    session.push_event(TelnetEvent::Negotiation {
+        command: CHAR_DO,
+        option: next,
+    });
+
+    if session.is_option_refused(next, Negotiation::Do) {
+        return Some(vec![CHAR_IAC, CHAR_WONT, next]);
+    }
+
+    if next == CHAR_ECHO && session.is_client_echoing() {
+        /* RFC-857: If both sides echoed, every char would be sent back and forth
+         * endlessly. */
+        session.is_echoing = false;
+        return Some(vec![CHAR_IAC, CHAR_WONT, CHAR_ECHO]);
+    }
+
+    if next == CHAR_ECHO {
+        if session.is_echoing {
+            /* Already enabled (e.g. offered via `send_command`), replying again would
+             * start a negotiation loop. */
+            return None;
+        }
+
+        session.is_echoing = true;
+        return Some(vec![CHAR_IAC, CHAR_WILL, CHAR_ECHO]);
+    }
+
+    if next == CHAR_TIMING_MARK {
+        /* RFC-860: All data that was sent before the mark has already been processed
+         * at this point, so the mark can be confirmed right away. This is a one-shot,
+         * nothing is enabled persistently. */
+        return Some(vec![CHAR_IAC, CHAR_WILL, CHAR_TIMING_MARK]);
+    }
+
+    if next == CHAR_END_OF_RECORD {
+        if session.is_end_of_record_active {
+            /* Already enabled, replying again would start a negotiation loop. */
+            return None;
+        }
+
+        session.is_end_of_record_active = true;
+        return Some(vec![CHAR_IAC, CHAR_WILL, CHAR_END_OF_RECORD]);
+    }
+
+    if next == CHAR_SUPPRESS_GO_AHEAD {
+        if session.is_suppressing_go_ahead {
+            /* Already enabled, replying again would start a negotiation loop. */
+            return None;
+        }
+
+        session.is_suppressing_go_ahead = true;
+        return Some(vec![CHAR_IAC, CHAR_WILL, CHAR_SUPPRESS_GO_AHEAD]);
+    }
+
+    if next == CHAR_TERMINAL_TYPE {
+        if session.is_sending_terminal_type {
+            /* Already enabled, replying again would start a negotiation loop. */
+            return None;
+        }
+
+        /* Client wants to know our terminal type, it's sent once it asks for it. */
+        session.is_sending_terminal_type = true;
+        return Some(vec![CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_TYPE]);
+    }
+
+    if next == CHAR_MSSP && !session.mssp_variables.is_empty() {
+        /* MSSP has no state, the variables are sent right away. */
+        let mut response = vec![CHAR_IAC, CHAR_WILL, CHAR_MSSP];
+        response.extend_from_slice(&build_mssp_sub_negotiation(&session.mssp_variables));
+        return Some(response);
+    }
+
+    #[cfg(feature = "mccp")]
+    if next == CHAR_MCCP2 {
+        if let CompressionState::Offered = session.compression {
+            /* Everything after this marker will be compressed. */
+            session.compression = CompressionState::Starting;
+            return Some(iac_subneg(CHAR_MCCP2, &[]));
+        }
+    }
+
+    /* Whatever they're asking for, we're not supporting it probably. */
+    Some(vec![CHAR_IAC, CHAR_WONT, next])
+}
+
+/// Answers the negotiation `IAC DONT next` of the client
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `next` - The negotiated option
+///
+/// # Returns
+///
+/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
+fn negotiate_dont(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
+    session.push_event(TelnetEvent::Negotiation {
+        command: CHAR_DONT,
+        option: next,
+    });
+
+    if next == CHAR_ECHO && !std::mem::take(&mut session.is_echoing) {
+        /* Not enabled, replying would start a negotiation loop. */
+        return None;
+    }
+
+    if next == CHAR_END_OF_RECORD && !std::mem::take(&mut session.is_end_of_record_active) {
+        /* Not enabled, replying would start a negotiation loop. */
+        return None;
+    }
+
+    if next == CHAR_SUPPRESS_GO_AHEAD && !std::mem::take(&mut session.is_suppressing_go_ahead) {
+        /* Not enabled, replying would start a negotiation loop. */
+        return None;
+    }
+
+    if next == CHAR_TERMINAL_TYPE && !std::mem::take(&mut session.is_sending_terminal_type) {
+        /* Not enabled, replying would start a negotiation loop. */
+        return None;
+    }
+
+    #[cfg(feature = "mccp")]
+    if next == CHAR_MCCP2 {
+        /* An active stream has to be finished first, see `take_pending_output` */
+        session.compression =
+            match std::mem::replace(&mut session.compression, CompressionState::Disabled) {
+                CompressionState::Active(encoder) | CompressionState::Ending(encoder) => {
+                    CompressionState::Ending(encoder)
+                }
+                _ => CompressionState::Disabled,
+            };
+    }
+
+    /* Whatever they're asking for, we're not supporting it probably. So it's fine to say that
+     * we won't do it. */
+    Some(vec![CHAR_IAC, CHAR_WONT, next])
+}
+
+/// Handles a completely received sub negotiation (`IAC SB option payload IAC SE`)
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `option` - The negotiated option
+/// * `payload` - The unescaped sub negotiation data
+///
+/// # Returns
+///
+/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
+fn handle_sub_negotiation(
+    session: &mut TelnetSession,
+    option: u8,
+    payload: &[u8],
+) -> Option<Vec<u8>> {
+    if let Some(observer) = session.observer.as_mut() {
+        observer.on_subneg(option, payload);
+    }
+
+    match (option, payload) {
+        (CHAR_LINEMODE, &[CHAR_LINEMODE_MODE, flags]) => {
+            session.linemode_flags = flags;
+
+            if flags & LINEMODE_EDIT != 0 && std::mem::take(&mut session.is_echoing) {
+                /* The client edits lines locally, echoing is up to the client now. */
+                return Some(iac_command(CHAR_WONT, CHAR_ECHO).to_vec());
+            }
+        }
+        (CHAR_NEW_ENVIRON, [CHAR_ENVIRON_IS | CHAR_ENVIRON_INFO, variables @ ..])
+            if session.remote_options.contains(&CHAR_NEW_ENVIRON) =>
+        {
+            update_environment(&mut session.environment, variables);
+
+            if session.is_charset_detection_enabled {
+                /* LC_ALL overrides LANG */
+                let encoding = ["LC_ALL", "LANG"]
+                    .iter()
+                    .filter_map(|name| session.environment.get(*name))
+                    .find_map(|locale| locale_charset(locale).and_then(Encoding::from_charset));
+
+                if let Some(encoding) = encoding {
+                    session.encoding = encoding;
+                }
+            }
+        }
+        (CHAR_NEW_ENVIRON, [CHAR_ENVIRON_IS | CHAR_ENVIRON_INFO, ..]) => {
+            /* NEW-ENVIRON isn't enabled (anymore), so ignore it. */
+        }
+        (CHAR_TERMINAL_TYPE, [CHAR_TERMINAL_TYPE_SEND]) if session.is_sending_terminal_type => {
+            let mut payload = vec![CHAR_TERMINAL_TYPE_IS];
+            payload.extend_from_slice(session.server_terminal_type.as_bytes());
+            return Some(iac_subneg(CHAR_TERMINAL_TYPE, &payload));
+        }
+        (CHAR_TERMINAL_TYPE, [CHAR_TERMINAL_TYPE_IS, terminal_type @ ..])
+            if session.remote_options.contains(&CHAR_TERMINAL_TYPE) =>
+        {
+            return update_terminal_type(session, terminal_type);
+        }
+        (CHAR_TERMINAL_TYPE, [CHAR_TERMINAL_TYPE_IS, ..]) => {
+            /* TTYPE isn't enabled (anymore), so ignore it. */
+        }
+        (CHAR_NAWS, &[width_high, width_low, height_high, height_low])
+            if session.remote_options.contains(&CHAR_NAWS) =>
+        {
+            session.window_size = Some((
+                u16::from_be_bytes([width_high, width_low]),
+                u16::from_be_bytes([height_high, height_low]),
+            ));
+        }
+        (CHAR_NAWS, _) if session.remote_options.contains(&CHAR_NAWS) => {
+            error!("Malformed window size: {payload:?}");
+            session.report_protocol_error(ProtocolError::MalformedSubNegotiation {
+                option: CHAR_NAWS,
+            });
+        }
+        (CHAR_NAWS, _) => { /* NAWS isn't enabled (anymore), so ignore it. */ }
+        (CHAR_TOGGLE_FLOW_CONTROL, &[command])
+            if session.remote_options.contains(&CHAR_TOGGLE_FLOW_CONTROL) =>
+        {
+            match command {
+                CHAR_FLOW_CONTROL_OFF => {
+                    session.is_flow_control_on = false;
+                    session.is_output_paused = false;
+                }
+                CHAR_FLOW_CONTROL_ON => session.is_flow_control_on = true,
+                CHAR_FLOW_CONTROL_RESTART_ANY => {
+                    session.flow_control_restart = Some(FlowControlRestart::Any);
+                }
+                CHAR_FLOW_CONTROL_RESTART_XON => {
+                    session.flow_control_restart = Some(FlowControlRestart::Xon);
+                }
+                _ => {
+                    error!("Not implemented flow control command: {command}");
+                    session.report_protocol_error(ProtocolError::MalformedSubNegotiation {
+                        option: CHAR_TOGGLE_FLOW_CONTROL,
+                    });
+                }
+            }
+        }
+        (CHAR_TOGGLE_FLOW_CONTROL, _) => {
+            /* LFLOW isn't enabled (anymore) or the command is malformed, so ignore it. */
+        }
+        (CHAR_X_DISPLAY_LOCATION, [CHAR_X_DISPLAY_LOCATION_IS, location @ ..])
+            if session.remote_options.contains(&CHAR_X_DISPLAY_LOCATION) =>
+        {
+            session.x_display_location = Some(String::from_utf8_lossy(location).into_owned());
+        }
+        (CHAR_X_DISPLAY_LOCATION, [CHAR_X_DISPLAY_LOCATION_IS, ..]) => {
+            /* XDISPLOC isn't enabled (anymore), so ignore it. */
+        }
+        (CHAR_SEND_LOCATION, location) if session.remote_options.contains(&CHAR_SEND_LOCATION) => {
+            session.client_location = Some(String::from_utf8_lossy(location).into_owned());
+        }
+        (CHAR_TERMINAL_SPEED, [CHAR_TERMINAL_SPEED_IS, speed @ ..])
+            if session.remote_options.contains(&CHAR_TERMINAL_SPEED) =>
+        {
+            match parse_terminal_speed(speed) {
+                Some(speed) => session.terminal_speed = Some(speed),
+                None => {
+                    error!("Malformed terminal speed: {speed:?}");
+                    session.report_protocol_error(ProtocolError::MalformedSubNegotiation {
+                        option: CHAR_TERMINAL_SPEED,
+                    });
+                }
+            }
+        }
+        (CHAR_TERMINAL_SPEED, [CHAR_TERMINAL_SPEED_IS, ..]) => {
+            /* TSPEED isn't enabled (anymore), so ignore it. */
+        }
+        (CHAR_AUTHENTICATION, &[CHAR_AUTHENTICATION_IS, auth_type, modifier, ref data @ ..])
+            if session.remote_options.contains(&CHAR_AUTHENTICATION) =>
+        {
+            let backend = session.auth_backend.as_mut()?;
+            let (is_authenticated, reply) = match backend.authenticate(auth_type, modifier, data) {
+                AuthDecision::Accept(reply) => (true, reply),
+                AuthDecision::Reject(reply) => (false, reply),
+            };
+
+            session.is_authenticated = is_authenticated;
+
+            let mut payload = vec![CHAR_AUTHENTICATION_REPLY, auth_type, modifier];
+            payload.extend_from_slice(&reply);
+            return Some(iac_subneg(CHAR_AUTHENTICATION, &payload));
+        }
+        _ => match session.sub_negotiation_fallback.as_mut() {
+            Some(fallback) => return fallback(option, payload),
+            None => error!("Not implemented sub negotiation for option: {option}"),
+        },
+    }
+
+    None
+}
+
+/// Updates the terminal type cycle of given `session` based on a TTYPE IS reply. The
+/// next terminal type is requested until the client repeats itself or reports its
+/// MTTS bitmask.
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `terminal_type` - The terminal type, as sent by the client
+///
+/// # Returns
+///
+/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
+fn update_terminal_type(session: &mut TelnetSession, terminal_type: &[u8]) -> Option<Vec<u8>> {
+    let terminal_type = String::from_utf8_lossy(terminal_type).into_owned();
+
+    if let Some(bitmask) = terminal_type.strip_prefix(MTTS_PREFIX) {
+        if let Ok(bits) = bitmask.trim().parse() {
+            /* MTTS is the last step of the cycle. */
+            session.terminal_capabilities = Some(TerminalCapabilities::from_bits(bits));
+            return None;
+        }
+    }
+
+    if session.terminal_types.contains(&terminal_type)
+        || session.terminal_types.len() >= MAX_TERMINAL_TYPES
+    {
+        /* Client repeats itself (e.g. A, B, A), so the cycle is complete. */
+        return None;
+    }
+
+    session.terminal_types.push(terminal_type);
+
+    if session.terminal_types.len() == MAX_TERMINAL_TYPES {
+        /* Client never repeats itself, so stop asking. */
+        return None;
+    }
+
+    Some(iac_subneg(CHAR_TERMINAL_TYPE, &[CHAR_TERMINAL_TYPE_SEND]))
+}
+
+/// Parses the terminal speed of a TSPEED IS reply, e.g. `38400,19200`
+///
+/// # Arguments
+///
+/// * `speed` - The terminal speed, as sent by the client
+///
+/// # Returns
+///
+/// The transmit and receive speed or `None` if `speed` is malformed.
+fn parse_terminal_speed(speed: &[u8]) -> Option<(u32, u32)> {
+    let speed = std::str::from_utf8(speed).ok()?;
+    let (transmit, receive) = speed.split_once(',')?;
+
+    Some((transmit.trim().parse().ok()?, receive.trim().parse().ok()?))
+}
+
+/// Builds the response to `IAC WILL option` for options whose data is requested via
+/// `IAC SB option SEND IAC SE`. If the option wasn't requested by the server yet, it's
+/// confirmed with `IAC DO option` first.
+///
+/// # Arguments
+///
+/// * `is_requested` - Whether the server requested the option, updated accordingly
+/// * `option` - The option the client is willing to send
+/// * `send` - The SEND byte of `option`
+fn build_send_request(is_requested: &mut bool, option: u8, send: u8) -> Vec<u8> {
+    let mut response = vec![];
+
+    if !*is_requested {
+        *is_requested = true;
+        response.extend_from_slice(&iac_command(CHAR_DO, option));
+    }
+
+    response.extend_from_slice(&iac_subneg(option, &[send]));
+    response
+}
+
+/// Updates given `session` in `TelnetState::AnsiEscapeSequence` based on `next` incoming byte
+///
 /// # Arguments
 ///
 /// * `session` - The affected `TelnetSession`
@@ -272,152 +4176,2632 @@ fn update_session_will(session: &mut TelnetSession, _next: u8) -> Option<Vec<u8>
 ///
 /// # Returns
 ///
+/// The next state of the session and, if `Some(Vec<u8>)`, the response that should be
+/// sent to the Telnet client.
+#[cfg(feature = "ansi")]
+fn update_session_escape_sequence(session: &mut TelnetSession, next: u8) -> Transition {
+    if session.escape_sequence.len() >= session.buffer_limits.escape_sequence {
+        /* Not a sane escape sequence, drop it. */
+        session.escape_sequence.clear();
+        let bell = Some(vec![CHAR_BEL]).filter(|_| session.is_bell_on_unknown_escape);
+        return (TelnetState::Idle, bell);
+    }
+
+    session.escape_sequence.push(next);
+
+    if !CHARS_ESCAPE_SEQUENCE_END.contains(&(next as char)) {
+        return (TelnetState::AnsiEscapeSequence, None);
+    }
+
+    let escape_sequence = std::mem::take(&mut session.escape_sequence);
+
+    if escape_sequence == BRACKETED_PASTE_START {
+        session.paste = Some(vec![]);
+        return (TelnetState::Idle, None);
+    }
+
+    if let Some(mut paste) = session.paste.take() {
+        if escape_sequence == BRACKETED_PASTE_END {
+            let text = session.encoding.decode(&paste);
+            session.push_event(TelnetEvent::Paste(text));
+        } else {
+            /* Pasted escape sequences are part of the pasted text */
+            paste.extend_from_slice(&escape_sequence);
+            session.paste = Some(paste);
+        }
+
+        return (TelnetState::Idle, None);
+    }
+
+    let sgr = match escape_sequence.as_slice() {
+        [CHAR_ESCAPE, b'[', params @ .., b'm'] => text::parse_sgr(params),
+        _ => vec![],
+    };
+    session.push_event(TelnetEvent::ControlSequence {
+        sequence: escape_sequence.clone(),
+        sgr,
+    });
+
+    if session.is_editing_lines {
+        if let Some(echo) = move_edit_cursor(session, &escape_sequence) {
+            return (TelnetState::Idle, Some(echo).filter(|e| !e.is_empty()));
+        }
+    }
+
+    if session.is_passing_escape_sequences {
+        session.push_data(escape_sequence.as_slice());
+        (TelnetState::Idle, Some(escape_sequence))
+    } else {
+        (TelnetState::Idle, None)
+    }
+}
+
+/// Updates given `environment` based on the variable list of a NEW-ENVIRON IS/INFO
+/// sub negotiation (`VAR name VALUE value USERVAR name ...`). Variables without a
+/// VALUE are undefined, so they're removed.
+///
+/// # Arguments
+///
+/// * `environment` - The environment that should be updated
+/// * `variables` - The variable list, as sent by the client
+fn update_environment(environment: &mut HashMap<String, String>, variables: &[u8]) {
+    /* Name and (if defined) value of the currently read variable */
+    let mut variable: Option<(Vec<u8>, Option<Vec<u8>>)> = None;
+    let mut bytes = variables.iter();
+
+    while let Some(&byte) = bytes.next() {
+        match (byte, variable.as_mut()) {
+            (CHAR_ENVIRON_VAR | CHAR_ENVIRON_USERVAR, _) => {
+                apply_environment_variable(environment, variable.take());
+                variable = Some((vec![], None));
+            }
+            (CHAR_ENVIRON_VALUE, Some((_, value))) => *value = Some(vec![]),
+            (_, Some((name, value))) => {
+                let byte = if byte == CHAR_ENVIRON_ESC {
+                    match bytes.next() {
+                        Some(&escaped) => escaped,
+                        None => break,
+                    }
+                } else {
+                    byte
+                };
+
+                match value {
+                    Some(value) => value.push(byte),
+                    None => name.push(byte),
+                }
+            }
+            (_, None) => { /* Data before the first type byte is malformed, ignore it. */ }
+        }
+    }
+
+    apply_environment_variable(environment, variable);
+}
+
+/// Applies a single, completely read NEW-ENVIRON variable to given `environment`
+///
+/// # Arguments
+///
+/// * `environment` - The environment that should be updated
+/// * `variable` - Name and value (`None` if undefined) of the variable
+fn apply_environment_variable(
+    environment: &mut HashMap<String, String>,
+    variable: Option<(Vec<u8>, Option<Vec<u8>>)>,
+) {
+    let Some((name, value)) = variable else {
+        return;
+    };
+
+    let name = String::from_utf8_lossy(&name).into_owned();
+    match value {
+        Some(_)
+            if environment.len() >= MAX_ENVIRONMENT_VARIABLES
+                && !environment.contains_key(&name) =>
+        {
+            error!("Too many environment variables, dropping {name}");
+        }
+        Some(value) => {
+            let value = String::from_utf8_lossy(&value).into_owned();
+            environment.insert(name, value);
+        }
+        None => {
+            environment.remove(&name);
+        }
+    }
+}
+
+/// Returns the echo of incoming data byte `next`, if the session is echoing. Printable
+/// chars are replaced by the echo mask, if set. The bytes of a multi-byte UTF-8 char
+/// are held back until the char is complete and echoed together. An incomplete char
+/// is echoed as is once any other byte arrives.
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `next` - The incoming data byte
+///
+/// # Returns
+///
 /// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
-fn update_session_wont(session: &mut TelnetSession, _next: u8) -> Option<Vec<u8>> {
-    /* Ignore message, just go back to idle state */
-    session.state = TelnetState::Idle;
-    None
+fn echo_data(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
+    let mut pending = std::mem::take(&mut session.echo_utf8_buffer);
+
+    if !session.is_echoing {
+        return None;
+    }
+
+    let is_continuation = next & 0xC0 == 0x80;
+
+    if is_continuation && !pending.is_empty() {
+        pending.push(next);
+
+        if utf8_sequence_length(pending[0]) == Some(pending.len()) {
+            return Some(echo_char(session, &pending));
+        }
+
+        session.echo_utf8_buffer = pending;
+        return None;
+    }
+
+    let mut echo: Vec<u8> = pending
+        .iter()
+        .flat_map(|&byte| echo_char(session, &[byte]))
+        .collect();
+
+    if utf8_sequence_length(next).is_some() {
+        session.echo_utf8_buffer.push(next);
+    } else {
+        echo.extend_from_slice(&echo_char(session, &[next]));
+    }
+
+    if !echo.is_empty() {
+        Some(echo)
+    } else {
+        None
+    }
+}
+
+/// Returns the echo of a single incoming char, which is either a single byte or a
+/// complete UTF-8 sequence
+///
+/// # Arguments
+///
+/// * `session` - The affected `TelnetSession`
+/// * `bytes` - The bytes of the incoming char
+fn echo_char(session: &TelnetSession, bytes: &[u8]) -> Vec<u8> {
+    match (session.echo_mask, bytes) {
+        (Some(mask), &[first, ..]) if first >= b' ' && first != CHAR_DELETE => {
+            let mut buffer = [0; 4];
+            mask.encode_utf8(&mut buffer).as_bytes().to_vec()
+        }
+        (_, &[CHAR_IAC]) => vec![CHAR_IAC, CHAR_IAC],
+        (_, &[control]) if session.is_caret_echo && control < b' ' => match control {
+            b'\r' | b'\n' | CHAR_TAB => bytes.to_vec(),
+            _ => vec![b'^', control + b'@'],
+        },
+        _ => bytes.to_vec(),
+    }
+}
+
+/// Returns the length of the UTF-8 sequence that starts with given byte, if it starts
+/// a multi-byte sequence
+///
+/// # Arguments
+///
+/// * `lead` - The first byte of the sequence
+fn utf8_sequence_length(lead: u8) -> Option<usize> {
+    match lead {
+        0xC2..=0xDF => Some(2),
+        0xE0..=0xEF => Some(3),
+        0xF0..=0xF4 => Some(4),
+        _ => None,
+    }
+}
+
+/// Builds the MSSP sub negotiation (`IAC SB MSSP VAR name VAL value ... IAC SE`) for
+/// given variables.
+///
+/// # Arguments
+///
+/// * `variables` - The variables (name and value) that should be reported
+fn build_mssp_sub_negotiation(variables: &[(String, String)]) -> Vec<u8> {
+    let mut payload = vec![];
+
+    for (name, value) in variables {
+        payload.push(CHAR_MSSP_VAR);
+        payload.extend_from_slice(name.as_bytes());
+        payload.push(CHAR_MSSP_VAL);
+        payload.extend_from_slice(value.as_bytes());
+    }
+
+    iac_subneg(CHAR_MSSP, &payload)
+}
+
+/// Returns the last line of given data buffer that is terminated by a line break
+/// (`\n`, `\r\n` or a bare `\r`), without that line break.
+///
+/// # Arguments
+///
+/// * `buffer` - Raw data buffer that ends with a line break
+/// * `encoding` - The encoding the line is decoded with
+fn current_line(buffer: &[u8], encoding: Encoding) -> String {
+    let content = match buffer.split_last() {
+        Some((b'\n' | b'\r', content)) => content,
+        _ => buffer,
+    };
+    let start = content
+        .iter()
+        .rposition(|&b| b == b'\r' || b == b'\n')
+        .map_or(0, |i| i + 1);
+
+    encoding.decode(&content[start..])
+}
+
+/// Returns the charset of given locale (e.g. `UTF-8` of `en_US.UTF-8@euro`), if any
+///
+/// # Arguments
+///
+/// * `locale` - The locale, e.g. the value of `LANG`
+fn locale_charset(locale: &str) -> Option<&str> {
+    let (_, charset) = locale.split_once('.')?;
+    charset.split('@').next()
+}
+
+/// Erases the current line from given text buffer. According to
+/// [RFC-854](https://www.rfc-editor.org/rfc/rfc854#page-13), the last CRLF should be kept.
+/// If there is none, the whole buffer is erased. This includes a trailing `\r` on its
+/// own, as it isn't a complete CRLF.
+///
+/// Arguments
+///
+/// * `buffer` - Text (or raw byte) buffer that should be updated
+fn erase_current_line<T: Eq + From<u8>>(buffer: &mut Vec<T>) {
+    let line_break = CHARS_LINE_BREAK.map(|c| T::from(c as u8));
+
+    /* Remove all chars until \r\n reached */
+    while !buffer.ends_with(&line_break) && buffer.pop().is_some() {}
 }
 
-/// Updates given `session` in `TelnetState::Do` based on `next` incoming byte
-///
-/// # Arguments
-///
-/// * `session` - The affected `TelnetSession`
-/// * `next` - The next incoming byte
-///
-/// # Returns
-///
-/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
-fn update_session_do(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
-    session.state = TelnetState::Idle;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TelnetTestDriver;
+
+    #[test]
+    fn erase_current_line_should_work() {
+        let mut buffer = vec!['a', 'b', 'c', '\r', '\n', 'd', 'e', 'f'];
+        erase_current_line(&mut buffer);
+        /* RFC-854: The recipient should delete characters from the data stream back to, but
+         * not including, the last "CR LF" sequence sent over the TELNET connection. */
+        assert_eq!(buffer, ['a', 'b', 'c', '\r', '\n']);
+
+        erase_current_line(&mut buffer);
+        assert_eq!(buffer, ['a', 'b', 'c', '\r', '\n']);
+
+        let mut buffer = vec!['a', 'b', 'c', 'd', 'e', 'f'];
+        erase_current_line(&mut buffer);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn erase_current_line_should_handle_short_buffers() {
+        let mut buffer = vec!['\r'];
+        erase_current_line(&mut buffer);
+        assert!(buffer.is_empty());
+
+        let mut buffer = vec!['\r', '\n'];
+        erase_current_line(&mut buffer);
+        assert_eq!(buffer, ['\r', '\n']);
+
+        let mut buffer = vec!['x'];
+        erase_current_line(&mut buffer);
+        assert!(buffer.is_empty());
+
+        let mut buffer: Vec<char> = vec![];
+        erase_current_line(&mut buffer);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn end_of_record_negotiation_should_work() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_END_OF_RECORD]),
+            Some(vec![CHAR_IAC, CHAR_WILL, CHAR_END_OF_RECORD])
+        );
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_END_OF_RECORD]),
+            None
+        );
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DONT, CHAR_END_OF_RECORD]),
+            Some(vec![CHAR_IAC, CHAR_WONT, CHAR_END_OF_RECORD])
+        );
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DONT, CHAR_END_OF_RECORD]),
+            None
+        );
+    }
+
+    #[test]
+    fn suppress_go_ahead_negotiation_should_work() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DONT, CHAR_SUPPRESS_GO_AHEAD]),
+            None
+        );
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_SUPPRESS_GO_AHEAD]),
+            Some(vec![CHAR_IAC, CHAR_WILL, CHAR_SUPPRESS_GO_AHEAD])
+        );
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_SUPPRESS_GO_AHEAD]),
+            None
+        );
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DONT, CHAR_SUPPRESS_GO_AHEAD]),
+            Some(vec![CHAR_IAC, CHAR_WONT, CHAR_SUPPRESS_GO_AHEAD])
+        );
+    }
+
+    #[test]
+    fn end_record_should_depend_on_negotiation() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(session.end_record(), [CHAR_IAC, CHAR_GO_AHEAD]);
+
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_END_OF_RECORD]);
+        assert_eq!(session.end_record(), [CHAR_IAC, CHAR_EOR]);
+
+        session.accept_data(&[CHAR_IAC, CHAR_DONT, CHAR_END_OF_RECORD]);
+        assert_eq!(session.end_record(), [CHAR_IAC, CHAR_GO_AHEAD]);
+    }
+
+    #[test]
+    fn prompt_should_end_with_negotiated_terminator() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(session.prompt("login: "), b"login: \xFF\xF9");
+
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_SUPPRESS_GO_AHEAD]);
+        assert_eq!(session.prompt("login: "), b"login: ");
+
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_END_OF_RECORD]);
+        assert_eq!(session.prompt("login: "), b"login: \xFF\xEF");
+
+        session.accept_data(&[CHAR_IAC, CHAR_DONT, CHAR_END_OF_RECORD]);
+        session.accept_data(&[CHAR_IAC, CHAR_DONT, CHAR_SUPPRESS_GO_AHEAD]);
+        assert_eq!(session.prompt("login: "), b"login: \xFF\xF9");
+    }
+
+    #[test]
+    fn teardown_should_disable_enabled_options() {
+        let mut session = TelnetSession::create(false);
+        session.set_option_policy(|option, _| option == CHAR_NAWS || option == CHAR_ECHO);
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NAWS]);
+
+        assert_eq!(
+            session.teardown(),
+            [CHAR_IAC, CHAR_WONT, CHAR_ECHO, CHAR_IAC, CHAR_DONT, CHAR_NAWS]
+        );
+        assert!(!session.is_remote_option_enabled(CHAR_NAWS));
+
+        /* Everything is disabled already */
+        assert!(session.teardown().is_empty());
+        assert_eq!(session.accept_data(b"a"), None);
+    }
+
+    #[test]
+    fn timing_mark_should_be_confirmed() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed(&[b'a', CHAR_IAC, CHAR_DO, CHAR_TIMING_MARK]);
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_IAC, CHAR_WILL, CHAR_TIMING_MARK][..])
+        );
+        assert_eq!(driver.data_string(), "a");
+
+        /* One-shot, so it's confirmed again */
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_TIMING_MARK]);
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_IAC, CHAR_WILL, CHAR_TIMING_MARK][..])
+        );
+        assert_eq!(
+            driver.events(),
+            vec![
+                TelnetEvent::Negotiation {
+                    command: CHAR_DO,
+                    option: CHAR_TIMING_MARK
+                };
+                2
+            ]
+        );
+    }
+
+    #[test]
+    fn command_should_be_split_across_calls() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+
+        /* Lone IAC at the end of the read */
+        driver.feed(&[0x41, CHAR_IAC]);
+        assert_eq!(driver.last_response(), None);
+        assert_eq!(driver.data_string(), "A");
+        assert!(driver.events().is_empty());
+
+        driver.feed(&[CHAR_WILL, CHAR_ECHO]);
+        assert_eq!(driver.data_string(), "A");
+        assert_eq!(
+            driver.events(),
+            [TelnetEvent::Negotiation {
+                command: CHAR_WILL,
+                option: CHAR_ECHO
+            }]
+        );
+    }
+
+    #[test]
+    fn state_should_report_sub_negotiation() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(session.state(), ParserState::Idle);
+
+        session.accept_data(&[CHAR_IAC]);
+        assert_eq!(session.state(), ParserState::Command);
+
+        session.accept_data(&[CHAR_SUB_NEGOTIATION, CHAR_NAWS, 0]);
+        assert_eq!(session.state(), ParserState::SubNegotiation);
+
+        session.accept_data(&[CHAR_IAC]);
+        assert_eq!(session.state(), ParserState::SubNegotiationIac);
+
+        session.accept_data(&[CHAR_SUB_NEGOTIATION_END]);
+        assert_eq!(session.state(), ParserState::Idle);
+    }
+
+    #[test]
+    fn arbitrary_input_should_respect_buffer_limits() {
+        let limits = BufferLimits {
+            data: 100,
+            sub_negotiation: 20,
+            escape_sequence: 8,
+            events: 10,
+        };
+        /* Deterministic xorshift, so failures are reproducible */
+        let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for round in 0..200 {
+            let mut session = TelnetSession::create(round % 2 == 0);
+            session.set_buffer_limits(limits);
+            session.set_mssp_var("NAME", "fuzz");
+            session.set_echo_mask(if round % 3 == 0 { Some('*') } else { None });
+
+            for _ in 0..50 {
+                let length = (random() % 64) as usize;
+                /* Bias towards special bytes to reach the interesting states */
+                let chunk: Vec<u8> = (0..length)
+                    .map(|_| match random() % 4 {
+                        0 => 240 + (random() % 16) as u8,
+                        1 => (random() % 40) as u8,
+                        _ => random() as u8,
+                    })
+                    .collect();
+
+                session.accept_data(&chunk);
+
+                assert!(session.stream.is_empty());
+                assert!(session.data_bytes().len() <= limits.data + 2);
+                assert!(session.terminal_types().len() <= MAX_TERMINAL_TYPES);
+                assert!(session.environment.len() <= MAX_ENVIRONMENT_VARIABLES);
+                assert_eq!(session.data.len(), session.data_bytes().len());
+                /* IAC SB option payload IAC */
+                assert!(session.parser.pending_bytes() <= limits.sub_negotiation + 4);
+                #[cfg(feature = "ansi")]
+                assert!(session.escape_sequence.len() <= limits.escape_sequence);
+                #[cfg(feature = "ansi")]
+                assert!(session
+                    .paste
+                    .as_ref()
+                    .is_none_or(|p| p.len() <= limits.data));
+                assert!(session.events.len() <= limits.events);
+            }
+
+            session.take_events();
+            session.clear_data_buffer();
+        }
+    }
+
+    #[test]
+    fn events_should_respect_buffer_limits() {
+        let mut session = TelnetSession::create(false);
+        session.set_buffer_limits(BufferLimits {
+            events: 2,
+            ..BufferLimits::default()
+        });
+        session.accept_data(b"a\nb\nc\n");
+
+        assert_eq!(
+            session.take_events(),
+            [
+                TelnetEvent::Line("a".to_string()),
+                TelnetEvent::Line("b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn oversized_sub_negotiation_should_be_dropped() {
+        let mut session = TelnetSession::create(false);
+        session.set_buffer_limits(BufferLimits {
+            sub_negotiation: 2,
+            ..BufferLimits::default()
+        });
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_TYPE]);
+
+        session.accept_data(&iac_subneg(CHAR_TERMINAL_TYPE, b"\0VT100"));
+        assert!(session.terminal_types().is_empty());
+
+        session.accept_data(&iac_subneg(CHAR_TERMINAL_TYPE, b"\0A"));
+        assert_eq!(session.terminal_types(), ["A"]);
+    }
+
+    #[test]
+    fn states_should_follow_transition_table() {
+        use ParserState::*;
+
+        /* Bytes that enter each state. The match is exhaustive, so a new state has to be
+         * added to it and to the table below. */
+        let enter = |state| -> &[u8] {
+            match state {
+                Idle => &[],
+                Command => &[CHAR_IAC],
+                CommandWill => &[CHAR_IAC, CHAR_WILL],
+                CommandWont => &[CHAR_IAC, CHAR_WONT],
+                CommandDo => &[CHAR_IAC, CHAR_DO],
+                CommandDont => &[CHAR_IAC, CHAR_DONT],
+                SubNegotiation => &[CHAR_IAC, CHAR_SUB_NEGOTIATION],
+                SubNegotiationIac => &[CHAR_IAC, CHAR_SUB_NEGOTIATION, CHAR_IAC],
+                #[cfg(feature = "ansi")]
+                AnsiEscapeSequence => &[CHAR_ESCAPE],
+            }
+        };
+
+        #[rustfmt::skip]
+        #[allow(unused_mut)]
+        let mut transitions = vec![
+            (Idle, b'a', Idle),
+            (Idle, b'\r', Idle),
+            (Idle, CHAR_BACK_SPACE, Idle),
+            (Idle, CHAR_IAC, Command),
+            (Command, CHAR_WILL, CommandWill),
+            (Command, CHAR_WONT, CommandWont),
+            (Command, CHAR_DO, CommandDo),
+            (Command, CHAR_DONT, CommandDont),
+            (Command, CHAR_SUB_NEGOTIATION, SubNegotiation),
+            (Command, CHAR_IAC, Idle),
+            (Command, CHAR_NOP, Idle),
+            (Command, CHAR_DATA_MARK, Idle),
+            (Command, 99, Idle),
+            (CommandWill, CHAR_ECHO, Idle),
+            (CommandWill, CHAR_IAC, Command),
+            (CommandWont, CHAR_ECHO, Idle),
+            (CommandWont, CHAR_IAC, Command),
+            (CommandDo, CHAR_ECHO, Idle),
+            (CommandDo, CHAR_IAC, Command),
+            (CommandDont, CHAR_ECHO, Idle),
+            (CommandDont, CHAR_IAC, Command),
+            (SubNegotiation, CHAR_TERMINAL_TYPE, SubNegotiation),
+            (SubNegotiation, CHAR_SUB_NEGOTIATION_END, SubNegotiation),
+            (SubNegotiation, CHAR_IAC, SubNegotiationIac),
+            (SubNegotiationIac, CHAR_IAC, SubNegotiation),
+            (SubNegotiationIac, CHAR_SUB_NEGOTIATION_END, Idle),
+            (SubNegotiationIac, CHAR_WILL, CommandWill),
+            (SubNegotiationIac, CHAR_NOP, Idle),
+        ];
+
+        #[cfg(feature = "ansi")]
+        transitions.extend([
+            (Idle, CHAR_ESCAPE, AnsiEscapeSequence),
+            (AnsiEscapeSequence, b'[', AnsiEscapeSequence),
+            (AnsiEscapeSequence, b'1', AnsiEscapeSequence),
+            (AnsiEscapeSequence, b'A', Idle),
+            (AnsiEscapeSequence, CHAR_IAC, Command),
+        ]);
+
+        for &(state, next, expected) in &transitions {
+            let mut session = TelnetSession::create(false);
+            session.accept_data(enter(state));
+            assert_eq!(session.state(), state);
+
+            session.accept_data(&[next]);
+            assert_eq!(session.state(), expected, "{state:?} on {next}");
+        }
+
+        /* Every state has to be covered by the table */
+        let covered: HashSet<&[u8]> = transitions
+            .iter()
+            .map(|&(from, _, _)| enter(from))
+            .collect();
+        assert_eq!(covered.len(), if cfg!(feature = "ansi") { 9 } else { 8 });
+    }
+
+    #[test]
+    fn iac_within_negotiation_should_start_new_command() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed(&[CHAR_IAC, CHAR_WILL, CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_IAC, CHAR_WILL, CHAR_ECHO][..])
+        );
+        assert_eq!(
+            driver.events(),
+            [TelnetEvent::Negotiation {
+                command: CHAR_DO,
+                option: CHAR_ECHO
+            }]
+        );
+        assert_eq!(driver.session().state(), ParserState::Idle);
+    }
+
+    #[test]
+    fn stray_command_should_abort_sub_negotiation() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
 
-    if next == CHAR_ECHO {
-        session.is_echoing = true;
-        return Some(vec![CHAR_IAC, CHAR_WILL, CHAR_ECHO]);
+        /* Reverse TTYPE is enabled, so a complete TTYPE SEND would be answered */
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_TERMINAL_TYPE]);
+        driver.feed(&[
+            CHAR_IAC,
+            CHAR_SUB_NEGOTIATION,
+            CHAR_TERMINAL_TYPE,
+            CHAR_TERMINAL_TYPE_SEND,
+            CHAR_IAC,
+            CHAR_WILL,
+            CHAR_ECHO,
+        ]);
+
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_IAC, CHAR_DONT, CHAR_ECHO][..])
+        );
+        assert_eq!(
+            driver.events().last(),
+            Some(&TelnetEvent::Negotiation {
+                command: CHAR_WILL,
+                option: CHAR_ECHO
+            })
+        );
+        assert_eq!(driver.session().state(), ParserState::Idle);
+
+        /* Following data isn't part of the aborted sub negotiation */
+        driver.feed(b"a\r\n");
+        assert_eq!(driver.data_string(), "a\r\n");
+    }
+
+    #[test]
+    fn partial_command_should_be_pending() {
+        let mut session = TelnetSession::create(false);
+        assert!(!session.has_partial_command());
+        assert_eq!(session.pending_bytes(), 0);
+
+        session.accept_data(&[CHAR_IAC]);
+        assert!(session.has_partial_command());
+        assert_eq!(session.pending_bytes(), 1);
+
+        session.accept_data(&[CHAR_SUB_NEGOTIATION, CHAR_TERMINAL_TYPE, CHAR_IAC]);
+        assert!(session.has_partial_command());
+        assert_eq!(session.pending_bytes(), 4);
+
+        session.accept_data(&[CHAR_SUB_NEGOTIATION_END, CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        assert!(!session.has_partial_command());
+        assert_eq!(session.pending_bytes(), 0);
+
+        /* The session echoes now, so a partial UTF-8 char is held back */
+        session.accept_data(&"ä".as_bytes()[..1]);
+        assert!(!session.has_partial_command());
+        assert_eq!(session.pending_bytes(), 1);
+    }
+
+    #[test]
+    fn doubled_iac_should_be_data() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed(&[CHAR_IAC, CHAR_IAC]);
+        assert_eq!(driver.last_response(), None);
+        assert_eq!(driver.session().get_data_buffer(), &[0xFF as char]);
+
+        /* Session is idle again */
+        driver.feed(b"a");
+        assert_eq!(driver.session().get_data_buffer(), &[0xFF as char, 'a']);
+    }
+
+    #[test]
+    fn data_mark_should_flush_buffer() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed(b"abc").feed(&[CHAR_IAC, CHAR_DATA_MARK]);
+        assert_eq!(driver.data_string(), "");
+        assert_eq!(driver.events(), [TelnetEvent::Synch]);
+
+        driver.feed(b"d");
+        assert_eq!(driver.data_string(), "d");
+    }
+
+    #[test]
+    fn dont_echo_should_only_disable_local_echo() {
+        let mut session = TelnetSession::builder().request(CHAR_ECHO).build();
+        session.initial_negotiation();
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_ECHO]);
+        assert!(session.is_client_echoing());
+
+        /* Both sides echoing would loop endlessly, so the local echo is refused */
+        let response = session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        assert_eq!(response, Some(vec![CHAR_IAC, CHAR_WONT, CHAR_ECHO]));
+        assert!(!session.is_echoing());
+
+        session.accept_data(&[CHAR_IAC, CHAR_DONT, CHAR_ECHO]);
+        assert!(!session.is_echoing());
+        assert!(session.is_client_echoing());
+    }
+
+    #[test]
+    fn wont_echo_should_only_disable_client_echo() {
+        let mut session = TelnetSession::builder().request(CHAR_ECHO).build();
+        session.initial_negotiation();
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_ECHO]);
+
+        let response = session.accept_data(&[CHAR_IAC, CHAR_WONT, CHAR_ECHO]);
+        assert_eq!(response, Some(vec![CHAR_IAC, CHAR_DONT, CHAR_ECHO]));
+        assert!(!session.is_client_echoing());
+
+        /* The local echo isn't affected, e.g. once the client asked for it */
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        let response = session.accept_data(&[CHAR_IAC, CHAR_WONT, CHAR_ECHO]);
+        assert_eq!(response, None);
+        assert!(session.is_echoing());
+    }
+
+    #[test]
+    fn client_echo_should_be_refused_while_echoing() {
+        let mut session = TelnetSession::create(false);
+        session.set_option_policy(|_, _| true);
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+
+        let response = session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_ECHO]);
+        assert_eq!(response, Some(vec![CHAR_IAC, CHAR_DONT, CHAR_ECHO]));
+        assert!(!session.is_client_echoing());
+    }
+
+    #[test]
+    fn nul_should_be_stripped() {
+        let mut session = TelnetSession::create(false);
+        session.accept_data(b"a\r\0b\0");
+        assert_eq!(session.data_bytes(), b"a\rb");
+
+        let mut session = TelnetSession::create(false);
+        session.set_nul_handling(NulHandling::Keep);
+        session.accept_data(b"a\r\0b");
+        assert_eq!(session.data_bytes(), b"a\r\0b");
+    }
+
+    #[test]
+    fn paused_input_should_be_processed_on_resume() {
+        let mut session = TelnetSession::create(false);
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        session.take_events();
+
+        session.pause_input();
+        assert_eq!(session.accept_data(b"ls\r\n"), None);
+        assert!(session.take_events().is_empty());
+        assert!(session.data_bytes().is_empty());
+
+        session.resume_input();
+        assert_eq!(session.take_output(), b"ls\r\n");
+        assert_eq!(session.take_events(), [TelnetEvent::Line("ls".to_string())]);
+    }
+
+    #[test]
+    fn paused_input_should_respect_buffer_limit() {
+        let mut session = TelnetSession::create(false);
+        session.set_buffer_limits(BufferLimits {
+            data: 4,
+            ..BufferLimits::default()
+        });
+
+        session.pause_input();
+        session.accept_data(b"abc");
+        session.accept_data(b"def");
+        session.resume_input();
+        assert_eq!(session.data_bytes(), b"abcd");
+    }
+
+    #[test]
+    fn flow_control_should_follow_lflow() {
+        let mut session = TelnetSession::create(false);
+        assert!(!session.is_flow_control_enabled());
+
+        let response = session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TOGGLE_FLOW_CONTROL]);
+        assert_eq!(
+            response,
+            Some(vec![CHAR_IAC, CHAR_DO, CHAR_TOGGLE_FLOW_CONTROL])
+        );
+        assert!(session.is_flow_control_enabled());
+
+        session.accept_data(&iac_subneg(
+            CHAR_TOGGLE_FLOW_CONTROL,
+            &[CHAR_FLOW_CONTROL_OFF],
+        ));
+        assert!(!session.is_flow_control_enabled());
+
+        session.accept_data(&iac_subneg(
+            CHAR_TOGGLE_FLOW_CONTROL,
+            &[CHAR_FLOW_CONTROL_ON],
+        ));
+        assert!(session.is_flow_control_enabled());
+
+        session.accept_data(&iac_subneg(
+            CHAR_TOGGLE_FLOW_CONTROL,
+            &[CHAR_FLOW_CONTROL_RESTART_XON],
+        ));
+        assert_eq!(
+            session.flow_control_restart(),
+            Some(FlowControlRestart::Xon)
+        );
+    }
+
+    #[test]
+    fn xoff_and_xon_should_toggle_output() {
+        let mut session = TelnetSession::create(false);
+        session.accept_data(&[CHAR_XOFF]);
+        assert!(!session.is_output_paused());
+        session.clear_data_buffer();
+
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TOGGLE_FLOW_CONTROL]);
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        session.accept_data(&[b'a', CHAR_XOFF]);
+        assert!(session.is_output_paused());
+
+        /* Echo is held back, negotiation replies aren't */
+        assert_eq!(session.accept_data(b"b"), None);
+        let response = session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_TIMING_MARK]);
+        assert_eq!(response, Some(vec![CHAR_IAC, CHAR_WILL, CHAR_TIMING_MARK]));
+
+        /* The echo of a wasn't taken before the XOFF, so it's held back as well */
+        assert_eq!(
+            session.accept_data(&[CHAR_XON, b'c']),
+            Some(b"abc".to_vec())
+        );
+        assert!(!session.is_output_paused());
+        assert_eq!(session.data_bytes(), b"abc");
+    }
+
+    #[test]
+    fn event_timestamps_should_not_decrease() {
+        let mut session = TelnetSession::create(false);
+        session.accept_data(b"a\r\n");
+        assert_eq!(session.take_timed_events()[0].timestamp, None);
+
+        session.set_event_timestamps(true);
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        session.accept_data(b"b\r\nc\r\n");
+
+        let timestamps: Vec<Instant> = session
+            .take_timed_events()
+            .into_iter()
+            .map(|timed_event| timed_event.timestamp.unwrap())
+            .collect();
+        assert_eq!(timestamps.len(), 3);
+        assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn caret_echo_should_show_control_chars() {
+        let mut session = TelnetSession::create(false);
+        session.set_caret_echo(true);
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+
+        assert_eq!(session.accept_data(&[0x01]), Some(b"^A".to_vec()));
+        assert_eq!(session.accept_data(b"b\r\n"), Some(b"b\r\n".to_vec()));
+        assert_eq!(session.data_bytes(), b"\x01b\r\n");
+    }
+
+    #[test]
+    #[cfg(feature = "ansi")]
+    fn escape_sequences_should_not_ring_bell() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(session.accept_data(b"\x1b[1m"), None);
+
+        /* Only sequences without known final byte ring the bell, if enabled */
+        session.set_buffer_limits(BufferLimits {
+            escape_sequence: 4,
+            ..Default::default()
+        });
+        session.set_bell_on_unknown_escape(true);
+        assert_eq!(session.accept_data(b"\x1b[1m"), None);
+        assert_eq!(session.accept_data(b"\x1b[123"), Some(vec![CHAR_BEL]));
+    }
+
+    #[test]
+    #[cfg(feature = "ansi")]
+    fn cursor_position_sequence_should_not_respond() {
+        let mut session = TelnetSession::create(false);
+        session.accept_data(b"\x1b[");
+
+        assert_eq!(update_session_escape_sequence(&mut session, b'H').1, None);
+        assert_eq!(session.accept_data(b"\x1b[H"), None);
+    }
+
+    #[test]
+    fn server_side_line_editing_should_assemble_line() {
+        let mut session = TelnetSession::create(false);
+        let negotiation = session.set_server_side_line_editing(true);
+        assert_eq!(
+            negotiation,
+            [
+                CHAR_IAC,
+                CHAR_WILL,
+                CHAR_ECHO,
+                CHAR_IAC,
+                CHAR_WILL,
+                CHAR_SUPPRESS_GO_AHEAD
+            ]
+        );
+
+        /* The client agreed to the offers, which needs no reply */
+        assert_eq!(
+            session.accept_data(&[
+                CHAR_IAC,
+                CHAR_DO,
+                CHAR_ECHO,
+                CHAR_IAC,
+                CHAR_DO,
+                CHAR_SUPPRESS_GO_AHEAD
+            ]),
+            None
+        );
+
+        assert_eq!(session.accept_data(b"abx"), Some(b"abx".to_vec()));
+        assert_eq!(
+            session.accept_data(&[CHAR_BACK_SPACE]),
+            Some(b"\x08 \x08".to_vec())
+        );
+        assert_eq!(session.accept_data(b"c\r\n"), Some(b"c\r\n".to_vec()));
+        assert_eq!(session.data_bytes(), b"abc\r\n");
+    }
+
+    #[test]
+    fn disabled_line_editing_should_only_revoke_own_echo() {
+        let mut session = TelnetSession::create(false);
+        session.set_server_side_line_editing(true);
+        let negotiation = session.set_server_side_line_editing(false);
+        assert_eq!(negotiation, [CHAR_IAC, CHAR_WONT, CHAR_ECHO]);
+        assert!(!session.is_echoing());
+
+        /* The client asked for the echo itself, so it's kept */
+        let mut session = TelnetSession::create(false);
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        let negotiation = session.set_server_side_line_editing(true);
+        assert_eq!(negotiation, [CHAR_IAC, CHAR_WILL, CHAR_SUPPRESS_GO_AHEAD]);
+        assert_eq!(session.set_server_side_line_editing(false), []);
+        assert!(session.is_echoing());
+    }
+
+    #[test]
+    #[cfg(feature = "ansi")]
+    fn server_side_line_editing_should_edit_at_cursor() {
+        let mut session = TelnetSession::create(false);
+        session.set_server_side_line_editing(true);
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        session.accept_data(b"acd");
+
+        /* Two chars left, insert b, then erase the c */
+        let response = session.accept_data(b"\x1b[D\x1b[Db");
+        assert_eq!(response, Some(b"\x08\x08bcd\x08\x08".to_vec()));
+        let response = session.accept_data(b"\x1b[C\x7f");
+        assert_eq!(response, Some(b"c\x08d \x08\x08".to_vec()));
+        assert_eq!(session.data_bytes(), b"abd");
+
+        /* Ctrl-U erases the whole line */
+        let response = session.accept_data(&[CHAR_NAK]);
+        assert_eq!(response, Some(b"\x08\x08   \x08\x08\x08".to_vec()));
+        assert_eq!(session.data_bytes(), b"");
+    }
+
+    #[test]
+    fn error_policy_should_decide_about_aborted_sub_negotiation() {
+        /* The sub negotiation is interrupted by IAC WILL ECHO */
+        let data = [
+            [CHAR_IAC, CHAR_SUB_NEGOTIATION, CHAR_NAWS, 0].as_slice(),
+            &[CHAR_IAC, CHAR_WILL, CHAR_ECHO],
+            b"a".as_slice(),
+        ]
+        .concat();
+        let error = ProtocolError::AbortedSubNegotiation {
+            option: Some(CHAR_NAWS),
+        };
+
+        let mut session = TelnetSession::create(false);
+        let response = session.accept_data(&data);
+        assert_eq!(response, Some(vec![CHAR_IAC, CHAR_DONT, CHAR_ECHO]));
+        assert!(!session
+            .take_events()
+            .contains(&TelnetEvent::ProtocolError(error)));
+        assert_eq!(session.data_bytes(), b"a");
+
+        let mut session = TelnetSession::create(false);
+        session.set_error_policy(ErrorPolicy::Recover);
+        let response = session.accept_data(&data);
+        assert_eq!(response, Some(vec![CHAR_IAC, CHAR_DONT, CHAR_ECHO]));
+        assert!(session
+            .take_events()
+            .contains(&TelnetEvent::ProtocolError(error)));
+        assert_eq!(session.protocol_error(), None);
+        assert_eq!(session.data_bytes(), b"a");
+
+        let mut session = TelnetSession::create(false);
+        session.set_error_policy(ErrorPolicy::Disconnect);
+        assert_eq!(session.accept_data(&data), None);
+        assert_eq!(session.take_events(), [TelnetEvent::ProtocolError(error)]);
+        assert_eq!(session.protocol_error(), Some(error));
+        assert!(session.data_bytes().is_empty());
+
+        session.accept_data(b"b");
+        assert!(session.data_bytes().is_empty());
+    }
+
+    #[test]
+    fn malformed_sub_negotiation_should_be_reported() {
+        let mut session = TelnetSession::builder().request(CHAR_NAWS).build();
+        session.set_error_policy(ErrorPolicy::Recover);
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NAWS]);
+        session.take_events();
+
+        session.accept_data(&iac_subneg(CHAR_NAWS, &[0, 80]));
+        assert_eq!(
+            session.take_events(),
+            [TelnetEvent::ProtocolError(
+                ProtocolError::MalformedSubNegotiation { option: CHAR_NAWS }
+            )]
+        );
+        assert_eq!(session.get_window_size(), None);
+    }
+
+    #[test]
+    fn echo_mask_should_hide_input() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        driver.session().set_echo_mask(Some('*'));
+
+        driver.feed_str("secret");
+        assert_eq!(driver.last_response(), Some(&b"******"[..]));
+        assert_eq!(driver.data_string(), "secret");
+
+        /* Erasing is echoed as usual */
+        driver.feed(&[CHAR_DELETE]);
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_BACK_SPACE, b' ', CHAR_BACK_SPACE][..])
+        );
+        assert_eq!(driver.data_string(), "secre");
+
+        driver.session().set_echo_mask(None);
+        driver.feed_str("t");
+        assert_eq!(driver.last_response(), Some(&b"t"[..]));
+    }
+
+    #[test]
+    fn echo_should_wait_for_complete_utf8_char() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+
+        /* "é" is 0xC3 0xA9 */
+        driver.feed(&[0xC3]);
+        assert_eq!(driver.last_response(), None);
+        driver.feed(&[0xA9]);
+        assert_eq!(driver.last_response(), Some(&[0xC3, 0xA9][..]));
+
+        /* An incomplete char is echoed as is */
+        driver.feed(&[0xE2, 0x82]).feed_str("a");
+        assert_eq!(driver.last_response(), Some(&[0xE2, 0x82, b'a'][..]));
+
+        driver.session().set_echo_mask(Some('*'));
+        driver.feed(&[0xC3, 0xA9]);
+        assert_eq!(driver.last_response(), Some(&b"*"[..]));
+    }
+
+    #[test]
+    fn tabs_should_be_expanded_to_tab_stops() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed_str("a\tb");
+        assert_eq!(driver.data_string(), "a\tb");
+
+        driver.session().clear_data_buffer();
+        driver.session().set_tab_stops(Some(4));
+        driver.feed_str("a\tb\tcdef\t\r\n\tx");
+        assert_eq!(driver.data_string(), "a   b   cdef    \r\n    x");
+
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]).feed_str("\t");
+        assert_eq!(driver.last_response(), Some(&b"   "[..]));
+    }
+
+    #[test]
+    #[cfg(feature = "ansi")]
+    fn bracketed_paste_should_be_single_event() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver
+            .feed_str("a\x1b[200~ls\r\n")
+            .feed_str("\x1b[1mrm\x1b[201")
+            .feed_str("~b\n");
+
+        assert_eq!(
+            driver.events(),
+            [
+                TelnetEvent::Paste("ls\r\n\x1b[1mrm".to_string()),
+                TelnetEvent::Line("ab".to_string())
+            ]
+        );
+        assert_eq!(driver.data_string(), "ab\n");
+    }
+
+    #[test]
+    #[cfg(feature = "ansi")]
+    fn pasted_control_chars_should_not_be_interpreted() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.session().set_tab_stops(Some(4));
+        driver.feed_str("a\x1b[200~b\x08\tc\x1b[201~");
+
+        assert_eq!(
+            driver.events(),
+            [TelnetEvent::Paste("b\x08\tc".to_string())]
+        );
+        assert_eq!(driver.data_string(), "a");
+    }
+
+    #[test]
+    #[cfg(feature = "ansi")]
+    fn sgr_sequences_should_be_decoded_in_events() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(true));
+        driver.feed_str("\x1b[1;31m\x1b[A");
+
+        assert_eq!(
+            driver.events(),
+            [
+                TelnetEvent::ControlSequence {
+                    sequence: b"\x1b[1;31m".to_vec(),
+                    sgr: vec![
+                        text::SgrAttribute::Bold,
+                        text::SgrAttribute::Foreground(text::PaletteColor::Basic(text::Color::Red))
+                    ],
+                },
+                TelnetEvent::ControlSequence {
+                    sequence: b"\x1b[A".to_vec(),
+                    sgr: vec![],
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn long_lines_should_be_rejected_with_bel() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.session().set_max_line_length(Some(5));
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+
+        driver.feed_str("abcdefg");
+        assert_eq!(driver.data_string(), "abcde");
+        assert_eq!(driver.last_response(), Some(&b"abcde\x07\x07"[..]));
+
+        driver.feed(&[CHAR_BACK_SPACE]).feed_str("xy");
+        assert_eq!(driver.data_string(), "abcdx");
+
+        /* The limit applies per line */
+        driver.feed_str("\r\n12345");
+        assert_eq!(driver.data_string(), "abcdx\r\n12345");
+    }
+
+    #[test]
+    fn full_data_buffer_should_still_complete_lines() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.session().set_buffer_limits(BufferLimits {
+            data: 4,
+            ..BufferLimits::default()
+        });
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+
+        /* Dropped bytes aren't echoed */
+        driver.feed_str("abcdef");
+        assert_eq!(driver.last_response(), Some(&b"abcd"[..]));
+
+        driver.feed_str("\r\n");
+        assert_eq!(driver.data_string(), "abcd\r\n");
+        assert_eq!(
+            driver.events().last(),
+            Some(&TelnetEvent::Line("abcd".to_string()))
+        );
+    }
+
+    #[test]
+    fn long_lines_should_limit_tab_expansion() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.session().set_max_line_length(Some(6));
+        driver.session().set_tab_stops(Some(4));
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+
+        driver.feed_str("abc\t\t\t");
+        assert_eq!(driver.data_string(), "abc   ");
+        assert_eq!(driver.last_response(), Some(&b"abc   \x07"[..]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "ansi"))]
+    fn escape_sequences_should_be_data_without_ansi() {
+        let mut session = TelnetSession::create(true);
+        session.accept_data(b"\x1b[Ax");
+
+        assert_eq!(session.data_bytes(), b"\x1b[Ax");
+        assert_eq!(session.state(), ParserState::Idle);
+    }
+
+    #[test]
+    fn completed_lines_should_be_events() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed_str("foo\r\nba").feed_str("r\n\n");
+        assert_eq!(
+            driver.events(),
+            [
+                TelnetEvent::Line("foo".to_string()),
+                TelnetEvent::Line("bar".to_string()),
+                TelnetEvent::Line("".to_string())
+            ]
+        );
+        assert_eq!(driver.data_string(), "foo\r\nbar\n\n");
+    }
+
+    #[test]
+    fn bare_carriage_return_should_complete_line() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed_str("foo\rbar\r");
+        assert_eq!(
+            driver.events(),
+            [
+                TelnetEvent::Line("foo".to_string()),
+                TelnetEvent::Line("bar".to_string())
+            ]
+        );
+
+        /* \r\n is a single line break, even if it's split across calls */
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed_str("foo\r").feed_str("\n");
+        assert_eq!(driver.events(), [TelnetEvent::Line("foo".to_string())]);
+    }
+
+    #[test]
+    fn linemode_mode_should_be_stored() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]).feed(b"a");
+        assert_eq!(driver.last_response(), Some(&b"a"[..]));
+        assert_eq!(driver.session().linemode_flags(), 0);
+
+        /* MODE EDIT | TRAPSIG, split across calls */
+        driver
+            .feed(&[CHAR_IAC, CHAR_SUB_NEGOTIATION, CHAR_LINEMODE])
+            .feed(&[CHAR_LINEMODE_MODE, 3, CHAR_IAC, CHAR_SUB_NEGOTIATION_END]);
+        assert_eq!(driver.session().linemode_flags(), 3);
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_IAC, CHAR_WONT, CHAR_ECHO][..])
+        );
+
+        /* Client edits locally, so the server stops echoing */
+        driver.feed(b"b");
+        assert_eq!(driver.last_response(), None);
+        assert_eq!(driver.data_string(), "ab");
+    }
+
+    #[test]
+    fn break_should_discard_partial_line() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed_str("ls\r\nrm -").feed(&[CHAR_IAC, CHAR_BREAK]);
+        assert_eq!(driver.data_string(), "ls\r\n");
+        assert_eq!(
+            driver.events(),
+            [TelnetEvent::Line("ls".to_string()), TelnetEvent::Break]
+        );
+    }
+
+    #[test]
+    fn data_bytes_should_be_exact() {
+        let mut session = TelnetSession::create(false);
+        session.set_nul_handling(NulHandling::Keep);
+
+        let mut driver = TelnetTestDriver::new(session);
+        driver.feed(&[0x00, 0x80, CHAR_IAC, CHAR_IAC, 0xC3, 0xA9, b'~']);
+        assert_eq!(
+            driver.session().data_bytes(),
+            [0x00, 0x80, 0xFF, 0xC3, 0xA9, b'~']
+        );
+
+        driver.feed(&[CHAR_BACK_SPACE]);
+        assert_eq!(
+            driver.session().data_bytes(),
+            [0x00, 0x80, 0xFF, 0xC3, 0xA9]
+        );
+
+        driver.feed(&[CHAR_ERASE_LINE]);
+        assert!(driver.session().data_bytes().is_empty());
+        assert!(driver.session().get_data_buffer().is_empty());
+    }
+
+    #[test]
+    fn accept_str_should_fill_data_buffer() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(session.accept_str("hello\r\n"), None);
+        assert_eq!(session.data_bytes(), b"hello\r\n");
+        assert_eq!(
+            session.take_events(),
+            [TelnetEvent::Line("hello".to_string())]
+        );
+    }
+
+    #[test]
+    fn option_policy_should_decide_negotiations() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver
+            .session()
+            .set_option_policy(|option, _| option == CHAR_SUPPRESS_GO_AHEAD);
+
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_SUPPRESS_GO_AHEAD]);
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_IAC, CHAR_WILL, CHAR_SUPPRESS_GO_AHEAD][..])
+        );
+
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_IAC, CHAR_WONT, CHAR_ECHO][..])
+        );
+
+        /* A refused ECHO is not enabled */
+        driver.feed_str("a");
+        assert_eq!(driver.last_response(), None);
+
+        driver.feed(&[CHAR_IAC, CHAR_WILL, CHAR_NEW_ENVIRON]);
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_IAC, CHAR_DONT, CHAR_NEW_ENVIRON][..])
+        );
+    }
+
+    #[test]
+    fn option_policy_should_not_accept_unimplemented_options() {
+        let mut session = TelnetSession::create(false);
+        session.set_option_policy(|_, _| true);
+
+        let response = session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_NAWS]);
+        assert_eq!(response, Some(vec![CHAR_IAC, CHAR_WONT, CHAR_NAWS]));
+    }
+
+    #[test]
+    fn will_should_be_answered() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver
+            .session()
+            .set_option_policy(|option, _| option == CHAR_NAWS);
+
+        driver.feed(&[CHAR_IAC, CHAR_WILL, CHAR_NAWS]);
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_IAC, CHAR_DO, CHAR_NAWS][..])
+        );
+        assert!(driver.session().is_remote_option_enabled(CHAR_NAWS));
+
+        /* Already enabled, so there's nothing to reply */
+        driver.feed(&[CHAR_IAC, CHAR_WILL, CHAR_NAWS]);
+        assert_eq!(driver.last_response(), None);
+
+        driver.feed(&[CHAR_IAC, CHAR_WILL, CHAR_SUPPRESS_GO_AHEAD]);
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_IAC, CHAR_DONT, CHAR_SUPPRESS_GO_AHEAD][..])
+        );
+        assert!(!driver
+            .session()
+            .is_remote_option_enabled(CHAR_SUPPRESS_GO_AHEAD));
+
+        driver.feed(&[CHAR_IAC, CHAR_WONT, CHAR_NAWS]);
+        assert_eq!(
+            driver.last_response(),
+            Some(&[CHAR_IAC, CHAR_DONT, CHAR_NAWS][..])
+        );
+        assert!(!driver.session().is_remote_option_enabled(CHAR_NAWS));
+
+        driver.feed(&[CHAR_IAC, CHAR_WONT, CHAR_NAWS]);
+        assert_eq!(driver.last_response(), None);
+    }
+
+    #[test]
+    fn observer_should_be_notified_in_order() {
+        struct RecordingObserver(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+        impl TelnetObserver for RecordingObserver {
+            fn on_line(&mut self, line: &str) {
+                self.0.lock().unwrap().push(format!("line {line}"));
+            }
+
+            fn on_option(&mut self, option: u8, enabled: bool) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("option {option} {enabled}"));
+            }
+
+            fn on_subneg(&mut self, option: u8, data: &[u8]) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("subneg {option} {data:?}"));
+            }
+        }
+
+        let calls = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let mut session =
+            TelnetSession::create(false).with_observer(RecordingObserver(calls.clone()));
+        let input = [
+            &b"a\r\n"[..],
+            &[CHAR_IAC, CHAR_DO, CHAR_ECHO],
+            &[CHAR_IAC, CHAR_DONT, CHAR_ECHO],
+            &iac_subneg(CHAR_LINEMODE, &[CHAR_LINEMODE_MODE, 0]),
+            b"b\n",
+        ]
+        .concat();
+        session.accept_data(&input);
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "line a",
+                "option 1 true",
+                "option 1 false",
+                "subneg 34 [1, 0]",
+                "line b"
+            ]
+        );
+        assert_eq!(session.take_events().len(), 4);
+    }
+
+    #[test]
+    fn resumed_session_should_keep_negotiated_options() {
+        let mut session = TelnetSession::create(false);
+        session.set_option_policy(|option, _| option == CHAR_NAWS || option == CHAR_ECHO);
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NAWS]);
+        session.accept_data(b"partial");
+
+        let mut resumed = TelnetSession::resume(session.session_token());
+
+        assert!(resumed.is_echoing());
+        assert!(resumed.is_remote_option_enabled(CHAR_NAWS));
+        assert!(resumed.data_bytes().is_empty());
+        assert_eq!(resumed.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NAWS]), None);
+        assert_eq!(resumed.accept_data(b"a"), Some(b"a".to_vec()));
+    }
+
+    #[test]
+    fn banner_should_follow_initial_negotiation() {
+        let mut session = TelnetSession::builder()
+            .request(CHAR_NAWS)
+            .request(CHAR_TERMINAL_TYPE)
+            .banner(b"Hi\xFF\nthere\r\n")
+            .normalize_banner_line_breaks(true)
+            .build();
+
+        assert_eq!(
+            session.initial_negotiation(),
+            [
+                &[CHAR_IAC, CHAR_DO, CHAR_NAWS][..],
+                &[CHAR_IAC, CHAR_DO, CHAR_TERMINAL_TYPE],
+                b"Hi\xFF\xFF\r\nthere\r\n"
+            ]
+            .concat()
+        );
+        assert!(session.initial_negotiation().is_empty());
+
+        /* Answers to the requests are accepted without another DO */
+        assert_eq!(session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NAWS]), None);
+        assert!(session.is_remote_option_enabled(CHAR_NAWS));
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_TYPE]),
+            Some(iac_subneg(CHAR_TERMINAL_TYPE, &[CHAR_TERMINAL_TYPE_SEND]))
+        );
+    }
+
+    /// Auth backend that accepts everyone
+    struct AcceptingBackend;
+
+    impl AuthBackend for AcceptingBackend {
+        fn auth_types(&self) -> Vec<(u8, u8)> {
+            vec![(99, 0), (98, 1)]
+        }
+
+        fn authenticate(&mut self, auth_type: u8, modifier: u8, data: &[u8]) -> AuthDecision {
+            assert_eq!((auth_type, modifier, data), (99, 0, &b"user\xFF"[..]));
+            AuthDecision::Accept(b"ok".to_vec())
+        }
+    }
+
+    #[test]
+    fn authentication_should_be_decided_by_backend() {
+        let mut session = TelnetSession::create(false);
+
+        /* Refused without a backend */
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_AUTHENTICATION]),
+            Some(vec![CHAR_IAC, CHAR_DONT, CHAR_AUTHENTICATION])
+        );
+
+        session.set_auth_backend(AcceptingBackend);
+        assert_eq!(
+            session.request_authentication(),
+            [CHAR_IAC, CHAR_DO, CHAR_AUTHENTICATION]
+        );
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_AUTHENTICATION]),
+            Some(iac_subneg(
+                CHAR_AUTHENTICATION,
+                &[CHAR_AUTHENTICATION_SEND, 99, 0, 98, 1]
+            ))
+        );
+        assert!(!session.is_authenticated());
+
+        assert_eq!(
+            session.accept_data(&iac_subneg(
+                CHAR_AUTHENTICATION,
+                &[&[CHAR_AUTHENTICATION_IS, 99, 0][..], b"user\xFF"].concat()
+            )),
+            Some(iac_subneg(
+                CHAR_AUTHENTICATION,
+                &[&[CHAR_AUTHENTICATION_REPLY, 99, 0][..], b"ok"].concat()
+            ))
+        );
+        assert!(session.is_authenticated());
+
+        session.accept_data(&[CHAR_IAC, CHAR_WONT, CHAR_AUTHENTICATION]);
+        assert!(!session.is_authenticated());
+    }
+
+    #[test]
+    fn output_crlf_should_convert_lone_line_feeds() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(session.prepare_output(b"a\nb"), b"a\nb");
+
+        session.set_output_crlf(true);
+        assert_eq!(session.prepare_output(b"a\nb"), b"a\r\nb");
+        assert_eq!(session.prepare_output(b"\n\r\n\n"), b"\r\n\r\n\r\n");
+
+        /* The carriage return may be part of the previous output */
+        assert_eq!(session.prepare_output(b"a\r"), b"a\r");
+        assert_eq!(session.prepare_output(b"\nb"), b"\nb");
+
+        /* Command sequences are left unchanged */
+        let commands = [
+            &[CHAR_IAC, CHAR_DO, b'\n'][..],
+            &iac_subneg(99, &[b'\n', CHAR_IAC, CHAR_SUB_NEGOTIATION_END, b'\n']),
+            &[CHAR_IAC, CHAR_IAC],
+            b"\n",
+        ]
+        .concat();
+        assert_eq!(
+            session.prepare_output(&commands),
+            [&commands[..commands.len() - 1], b"\r\n"].concat()
+        );
+    }
+
+    #[test]
+    fn describe_command_should_name_commands_and_options() {
+        assert_eq!(
+            describe_command(&[CHAR_IAC, CHAR_WILL, CHAR_ECHO]),
+            "IAC WILL ECHO"
+        );
+        assert_eq!(describe_command(&[CHAR_IAC, CHAR_DONT, 99]), "IAC DONT 99");
+        assert_eq!(describe_command(&[CHAR_IAC, CHAR_NOP]), "IAC NOP");
+        assert_eq!(describe_command(&[CHAR_IAC, CHAR_IAC]), "IAC IAC");
+        assert_eq!(describe_command(b"a\tb"), r#""a\tb""#);
+        assert_eq!(describe_command(&[]), "");
+    }
+
+    #[test]
+    fn describe_command_should_handle_sub_negotiations() {
+        assert_eq!(
+            describe_command(&iac_subneg(CHAR_NAWS, &[0, 80, CHAR_IAC, 24])),
+            "IAC SB NAWS 0 \"P\" IAC IAC 24 IAC SE"
+        );
+
+        /* Bytes after the end are data again */
+        assert_eq!(
+            describe_command(&[&iac_subneg(CHAR_TERMINAL_TYPE, &[1])[..], b"1"].concat()),
+            "IAC SB TERMINAL-TYPE 1 IAC SE \"1\""
+        );
+
+        /* Stray commands abort the sub negotiation */
+        assert_eq!(
+            describe_command(&[
+                CHAR_IAC,
+                CHAR_SUB_NEGOTIATION,
+                24,
+                1,
+                CHAR_IAC,
+                CHAR_WILL,
+                1
+            ]),
+            "IAC SB TERMINAL-TYPE 1 IAC WILL ECHO"
+        );
+
+        /* Incomplete sequences are rendered as far as they go */
+        assert_eq!(
+            describe_command(&[CHAR_IAC, CHAR_SUB_NEGOTIATION, CHAR_ECHO, 1]),
+            "IAC SB ECHO 1"
+        );
     }
 
-    /* Whatever they're asking for, we're not supporting it probably. */
-    Some(vec![CHAR_IAC, CHAR_WONT, next])
-}
+    #[test]
+    fn description_should_be_parsed_back() {
+        let sequences = [
+            [&[CHAR_IAC, CHAR_DO, CHAR_ECHO][..], b"a\tb\"'\\\x01\xFF"].concat(),
+            iac_subneg(CHAR_NAWS, &[0, 80, CHAR_IAC, 24]),
+            vec![
+                CHAR_IAC, CHAR_IAC, CHAR_IAC, CHAR_DONT, 99, CHAR_IAC, CHAR_NOP,
+            ],
+        ];
 
-/// Updates given `session` in `TelnetState::Dont` based on `next` incoming byte
-///
-/// # Arguments
-///
-/// * `session` - The affected `TelnetSession`
-/// * `next` - The next incoming byte
-///
-/// # Returns
-///
-/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
-fn update_session_dont(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
-    session.state = TelnetState::Idle;
+        for sequence in sequences {
+            let description = describe_command(&sequence);
+            assert_eq!(parse_description(&description), Some(sequence));
+        }
 
-    if next == CHAR_ECHO {
-        session.is_echoing = false;
+        assert_eq!(parse_description(r#""unterminated"#), None);
+        assert_eq!(parse_description(r#""\q""#), None);
     }
 
-    /* Whatever they're asking for, we're not supporting it probably. So it's fine to say that
-     * we won't do it. */
-    Some(vec![CHAR_IAC, CHAR_WONT, next])
-}
+    #[test]
+    fn binary_garbage_should_be_suspected() {
+        /* Deterministic xorshift, so failures are reproducible */
+        let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+        let garbage: Vec<u8> = (0..100)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect();
 
-/// Updates given `session` in `TelnetState::SubNegotiation` based on `next` incoming byte
-///
-/// # Arguments
-///
-/// * `session` - The affected `TelnetSession`
-/// * `next` - The next incoming byte
-///
-/// # Returns
-///
-/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
-fn update_session_sub_negotiation(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
-    /* We're NOT handling sub negotiations right now. */
-    if next == CHAR_SUB_NEGOTIATION_END {
-        session.state = TelnetState::Idle;
+        let mut session = TelnetSession::create(false);
+        session.accept_data(&garbage[..50]);
+        assert!(!session
+            .take_events()
+            .contains(&TelnetEvent::SuspectedNonTelnetClient));
+        session.accept_data(&garbage[50..]);
+        assert!(session
+            .take_events()
+            .contains(&TelnetEvent::SuspectedNonTelnetClient));
+
+        /* Text isn't suspicious, even if it's not ASCII */
+        let mut session = TelnetSession::create(false);
+        session.accept_data("Grüße, привет, 你好! ".repeat(4).as_bytes());
+        assert!(!session
+            .take_events()
+            .contains(&TelnetEvent::SuspectedNonTelnetClient));
     }
 
-    None
-}
+    /// Writer into a buffer that's shared with the test
+    #[derive(Clone)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
 
-/// Updates given `session` in `TelnetState::AnsiEscapeSequence` based on `next` incoming byte
-///
-/// # Arguments
-///
-/// * `session` - The affected `TelnetSession`
-/// * `next` - The next incoming byte
-///
-/// # Returns
-///
-/// If `Some(Vec<u8>)` is returned, it should be sent to the Telnet client.
-fn update_session_escape_sequence(session: &mut TelnetSession, next: u8) -> Option<Vec<u8>> {
-    if let Some(buffer) = session.ansi_escape_sequence_buffer.as_mut() {
-        buffer.push(next as char);
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
 
-        if !CHARS_ESCAPE_SEQUENCE_END.contains(&(next as char)) {
-            return None;
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
+    }
 
-        let escape_sequence = buffer.iter().map(|&c| c as u8).collect();
+    #[test]
+    fn trace_should_record_negotiation() {
+        let buffer = SharedBuffer(Default::default());
+        let mut session = TelnetSession::create(false);
+        /* The client agreed to send its terminal type before the trace started */
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_TYPE]);
+        session.set_trace(buffer.clone());
 
-        session.state = TelnetState::Idle;
-        session.data.extend_from_slice(buffer.as_slice());
-        buffer.clear();
+        session.accept_data(&[&[CHAR_IAC, CHAR_DO, CHAR_ECHO][..], b"hi\r\n"].concat());
+        session.accept_data(&iac_subneg(CHAR_TERMINAL_TYPE, b"\0VT100"));
 
-        Some(escape_sequence)
-    } else {
-        if !CHARS_ESCAPE_SEQUENCE_END.contains(&(next as char)) {
-            return None;
+        let trace = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = trace.lines().collect::<Vec<&str>>();
+
+        let expected = [
+            r#"< IAC DO ECHO "hi\r\n""#,
+            "> IAC WILL ECHO",
+            r#"> "hi\r\n""#,
+            r#"< IAC SB TERMINAL-TYPE 0 "VT100" IAC SE"#,
+            "> IAC SB TERMINAL-TYPE 1 IAC SE",
+        ];
+
+        assert_eq!(lines.len(), expected.len());
+        for (line, expected) in lines.iter().zip(expected) {
+            /* Every line starts with the timestamp */
+            let (timestamp, rest) = line.split_once(' ').unwrap();
+            assert!(timestamp.parse::<f64>().is_ok());
+            assert_eq!(rest, expected);
         }
+    }
 
-        session.state = TelnetState::Idle;
+    #[test]
+    fn unanswered_request_should_time_out() {
+        let mut session = TelnetSession::builder().request(CHAR_NAWS).build();
+        session.set_negotiation_timeout(Duration::from_secs(5));
+        session.initial_negotiation();
+        let start = Instant::now();
+
+        session.tick(start + Duration::from_secs(1));
+        assert!(session.take_events().is_empty());
 
-        Some(vec![CHAR_BEL])
+        session.tick(start + Duration::from_secs(6));
+        assert_eq!(
+            session.take_events(),
+            [TelnetEvent::NegotiationTimeout { option: CHAR_NAWS }]
+        );
+        session.tick(start + Duration::from_secs(7));
+        assert!(session.take_events().is_empty());
+
+        /* A late answer is refused, as the option is unavailable by now */
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NAWS]),
+            Some(vec![CHAR_IAC, CHAR_DONT, CHAR_NAWS])
+        );
+        assert!(!session.is_remote_option_enabled(CHAR_NAWS));
+        assert_eq!(
+            session.accept_data(&iac_subneg(CHAR_NAWS, &[0, 80, 0, 24])),
+            None
+        );
+        assert_eq!(session.get_window_size(), None);
     }
-}
 
-/// Erases the current line from given text buffer. According to
-/// [RFC-854](https://www.rfc-editor.org/rfc/rfc854#page-13), the last CRLF should be kept.
-///
-/// Arguments
-///
-/// * `buffer` - Text buffer that should be updated
-fn erase_current_line(buffer: &mut Vec<char>) {
-    loop {
-        /* Remove all chars until \r\n reached */
-        if buffer.len() < 2 {
-            buffer.clear();
-            break;
-        }
+    #[test]
+    fn unanswered_terminal_type_request_should_time_out() {
+        let mut session = TelnetSession::create(false);
+        session.set_negotiation_timeout(Duration::from_secs(5));
+        session.request_terminal_type();
+        let start = Instant::now();
+
+        session.tick(start + Duration::from_secs(6));
+        assert_eq!(
+            session.take_events(),
+            [TelnetEvent::NegotiationTimeout {
+                option: CHAR_TERMINAL_TYPE
+            }]
+        );
+
+        /* An answered request doesn't time out */
+        session.send_command(CHAR_DO, CHAR_TERMINAL_TYPE);
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_TYPE]);
+        session.take_events();
+        session.tick(start + Duration::from_secs(60));
+        assert!(session.take_events().is_empty());
+    }
+
+    #[test]
+    fn subneg_fallback_should_handle_unknown_options() {
+        let mut session = TelnetSession::create(false);
+
+        /* Discarded without a fallback */
+        assert_eq!(session.accept_data(&iac_subneg(99, b"abc")), None);
+
+        session.set_subneg_fallback(|option, payload| match option {
+            99 => Some(iac_subneg(option, payload)),
+            _ => None,
+        });
+        assert_eq!(
+            session.accept_data(&iac_subneg(99, b"a\xFFc")),
+            Some(iac_subneg(99, b"a\xFFc"))
+        );
+        assert_eq!(session.accept_data(&iac_subneg(98, b"abc")), None);
+
+        /* Known options are not passed to the fallback */
+        session.accept_data(&iac_subneg(CHAR_LINEMODE, &[CHAR_LINEMODE_MODE, 3]));
+        assert_eq!(session.linemode_flags(), 3);
+    }
+
+    #[test]
+    fn unknown_commands_should_return_to_idle() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(session.accept_data(&[CHAR_IAC, 99]), None);
+        session.accept_data(b"abc");
+        assert_eq!(session.data_bytes(), b"abc");
+
+        session.set_unknown_command_handler(|command| Some(vec![command]));
+        assert_eq!(session.accept_data(&[CHAR_IAC, 99]), Some(vec![99]));
+        assert_eq!(session.accept_data(&[CHAR_IAC, CHAR_NOP]), None);
+        session.accept_data(b"d");
+        assert_eq!(session.data_bytes(), b"abcd");
+    }
+
+    #[test]
+    fn data_after_unknown_command_should_not_be_swallowed() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.feed(&[CHAR_IAC, 0x99, 0x41, 0x42]);
+
+        assert_eq!(driver.data_string(), "AB");
+        assert_eq!(driver.session().state(), ParserState::Idle);
+    }
+
+    #[test]
+    fn input_filters_should_transform_lines() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver
+            .session()
+            .add_input_filter(|line| line.make_ascii_uppercase());
+        driver
+            .session()
+            .add_input_filter(|line| line.retain(|&c| c != b'L'));
+        driver.feed_str("hello\r\n");
+
+        assert_eq!(driver.events(), [TelnetEvent::Line("HEO".to_string())]);
+        assert_eq!(driver.data_string(), "HEO\r\n");
+    }
+
+    #[test]
+    fn lines_should_be_taken_from_buffer() {
+        let mut session = TelnetSession::create(false);
+        session.accept_data(b"a\r\nb\r\nc");
+        assert_eq!(session.lines().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(session.data_bytes(), b"c");
+        assert_eq!(session.get_data_buffer(), &['c']);
+
+        session.accept_data(b"\nd\re");
+        assert_eq!(session.lines().collect::<Vec<_>>(), ["c", "d"]);
+
+        /* The \n of a \r\n that was split by taking the line is dropped */
+        session.accept_data(b"\r");
+        assert_eq!(session.take_line(), Some("e".to_string()));
+        session.accept_data(b"\nf");
+        assert_eq!(session.take_line(), None);
+        assert_eq!(session.data_bytes(), b"f");
+    }
+
+    #[test]
+    fn commands_within_utf8_sequence_should_be_processed() {
+        let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+        driver.session().set_encoding(Encoding::Utf8);
+        driver.feed(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+
+        driver.feed(&[0xC3, CHAR_IAC, CHAR_NOP]);
+        assert_eq!(driver.last_response(), None);
+        assert_eq!(driver.session().state(), ParserState::Idle);
+
+        driver.feed(&[0xA9]);
+        assert_eq!(driver.last_response(), Some("é".as_bytes()));
+
+        driver.feed(b"\r\n");
+        assert_eq!(driver.session().data_bytes(), "é\r\n".as_bytes());
+        assert_eq!(
+            driver.events().last(),
+            Some(&TelnetEvent::Line("é".to_string()))
+        );
+    }
+
+    #[test]
+    fn consecutive_responses_should_be_coalesced() {
+        let buffer = SharedBuffer(Default::default());
+        let mut session = TelnetSession::create(false);
+        session.set_trace(buffer.clone());
+        session.receive(&[CHAR_IAC, CHAR_DO, CHAR_ECHO, CHAR_IAC, CHAR_DO, CHAR_EOR]);
+        session.receive(b"abc");
+
+        assert_eq!(
+            session.take_output(),
+            [
+                &[CHAR_IAC, CHAR_WILL, CHAR_ECHO, CHAR_IAC, CHAR_WONT, CHAR_EOR][..],
+                b"abc"
+            ]
+            .concat()
+        );
+
+        /* Each response is prepared for sending as a single chunk */
+        let trace = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let sent = trace
+            .lines()
+            .filter_map(|line| line.split_once(" > "))
+            .map(|(_, data)| data)
+            .collect::<Vec<&str>>();
+        assert_eq!(sent, ["IAC WILL ECHO IAC WONT 239", r#""abc""#]);
+    }
+
+    #[test]
+    fn commands_and_data_should_be_taken_separately() {
+        let mut session = TelnetSession::create(false);
+        session.receive(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]);
+        session.receive(b"ab");
+        session.receive(&[CHAR_IAC, CHAR_DO, CHAR_END_OF_RECORD, b'c']);
+
+        assert_eq!(session.take_data(), b"abc");
+        assert_eq!(
+            session.take_commands(),
+            [
+                CHAR_IAC,
+                CHAR_WILL,
+                CHAR_ECHO,
+                CHAR_IAC,
+                CHAR_WILL,
+                CHAR_END_OF_RECORD
+            ]
+        );
+        assert!(session.take_output().is_empty());
+
+        session.receive(b"d");
+        session.receive(&[CHAR_IAC, CHAR_DONT, CHAR_END_OF_RECORD]);
+        assert_eq!(
+            session.take_output(),
+            [b'd', CHAR_IAC, CHAR_WONT, CHAR_END_OF_RECORD]
+        );
+    }
+
+    #[test]
+    fn run_blocking_should_stop_on_fatal_error() {
+        /* IAC WILL IAC NOP is an incomplete negotiation */
+        let mut reader = std::io::Cursor::new([CHAR_IAC, CHAR_WILL, CHAR_IAC, CHAR_NOP])
+            .chain(std::io::Cursor::new(b"a"));
+
+        let mut session = TelnetSession::create(false);
+        session.set_error_policy(ErrorPolicy::Disconnect);
+        session.run_blocking(&mut reader, vec![]).unwrap();
+
+        assert_eq!(
+            session.protocol_error(),
+            Some(ProtocolError::IncompleteNegotiation)
+        );
+        assert_eq!(reader.get_ref().1.position(), 0);
+    }
+
+    #[test]
+    fn run_blocking_should_drive_session() {
+        let input = [&[CHAR_IAC, CHAR_DO, CHAR_ECHO][..], b"hi\r\n"].concat();
+        let mut output = vec![];
+
+        let mut session = TelnetSession::create(false);
+        session
+            .run_blocking(std::io::Cursor::new(input), &mut output)
+            .unwrap();
+
+        assert_eq!(
+            output,
+            [&[CHAR_IAC, CHAR_WILL, CHAR_ECHO][..], b"hi\r\n"].concat()
+        );
+        assert_eq!(
+            session.take_events(),
+            [
+                TelnetEvent::Negotiation {
+                    command: CHAR_DO,
+                    option: CHAR_ECHO
+                },
+                TelnetEvent::Line("hi".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn lang_should_set_encoding() {
+        let build_reply = |name: &[u8], value: &[u8]| {
+            let payload = [
+                &[CHAR_ENVIRON_IS, CHAR_ENVIRON_VAR][..],
+                name,
+                &[CHAR_ENVIRON_VALUE],
+                value,
+            ]
+            .concat();
+            iac_subneg(CHAR_NEW_ENVIRON, &payload)
+        };
+
+        let mut session = TelnetSession::create(false);
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NEW_ENVIRON]);
+        session.take_events();
+        session.accept_data("\u{e9}\r\n".as_bytes());
+        assert_eq!(session.encoding(), Encoding::Latin1);
+
+        session.accept_data(&build_reply(b"LANG", b"en_US.UTF-8"));
+        assert_eq!(session.encoding(), Encoding::Utf8);
+        session.accept_data("\u{e9}\r\n".as_bytes());
+        assert_eq!(
+            session.take_events(),
+            [
+                TelnetEvent::Line("\u{c3}\u{a9}".to_string()),
+                TelnetEvent::Line("\u{e9}".to_string())
+            ]
+        );
+
+        /* Unsupported charsets are ignored */
+        session.accept_data(&build_reply(b"LANG", b"ja_JP.eucJP"));
+        assert_eq!(session.encoding(), Encoding::Utf8);
+        session.accept_data(&build_reply(b"LC_ALL", b"de_DE.ISO-8859-1@euro"));
+        assert_eq!(session.encoding(), Encoding::Latin1);
+
+        let mut session = TelnetSession::create(false);
+        session.set_charset_detection(false);
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NEW_ENVIRON]);
+        session.accept_data(&build_reply(b"LANG", b"C.UTF-8"));
+        assert_eq!(session.encoding(), Encoding::Latin1);
+    }
+
+    #[test]
+    fn environment_should_be_requested() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(
+            session.request_environment(),
+            [CHAR_IAC, CHAR_DO, CHAR_NEW_ENVIRON]
+        );
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NEW_ENVIRON]),
+            Some(vec![
+                CHAR_IAC,
+                CHAR_SUB_NEGOTIATION,
+                CHAR_NEW_ENVIRON,
+                CHAR_ENVIRON_SEND,
+                CHAR_IAC,
+                CHAR_SUB_NEGOTIATION_END
+            ])
+        );
+    }
+
+    #[test]
+    fn environment_should_be_parsed() {
+        let mut session = TelnetSession::create(false);
+        let reply = [
+            &[
+                CHAR_IAC,
+                CHAR_SUB_NEGOTIATION,
+                CHAR_NEW_ENVIRON,
+                CHAR_ENVIRON_IS,
+            ][..],
+            &[CHAR_ENVIRON_VAR],
+            b"USER",
+            &[CHAR_ENVIRON_VALUE],
+            b"admin",
+            &[CHAR_ENVIRON_USERVAR],
+            b"ES",
+            &[CHAR_ENVIRON_ESC, CHAR_ENVIRON_VALUE],
+            b"C",
+            &[CHAR_ENVIRON_VALUE],
+            b"x",
+            &[CHAR_ENVIRON_VAR],
+            b"DISPLAY",
+            &[CHAR_IAC, CHAR_SUB_NEGOTIATION_END],
+        ]
+        .concat();
+
+        /* NEW-ENVIRON isn't enabled yet, so the variables are ignored */
+        session.accept_data(&reply);
+        assert!(session.environment().is_empty());
+
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NEW_ENVIRON]);
+        session.accept_data(&reply);
+
+        let mut expected = HashMap::new();
+        expected.insert("USER".to_string(), "admin".to_string());
+        expected.insert("ES\u{1}C".to_string(), "x".to_string());
+        assert_eq!(session.environment(), &expected);
 
-        let start_index = buffer.len() - 2;
-        if contains_sequence(&buffer[start_index..], &CHARS_LINE_BREAK) {
-            break;
+        /* INFO updates single variables, undefined variables are removed */
+        let info = [
+            &[
+                CHAR_IAC,
+                CHAR_SUB_NEGOTIATION,
+                CHAR_NEW_ENVIRON,
+                CHAR_ENVIRON_INFO,
+            ][..],
+            &[CHAR_ENVIRON_VAR],
+            b"USER",
+            &[CHAR_IAC, CHAR_SUB_NEGOTIATION_END],
+        ]
+        .concat();
+        session.accept_data(&info);
+        assert!(!session.environment().contains_key("USER"));
+    }
+
+    #[test]
+    fn environment_should_be_limited() {
+        let mut payload = vec![CHAR_ENVIRON_INFO];
+        for i in 0..100 {
+            payload.push(CHAR_ENVIRON_VAR);
+            payload.extend_from_slice(format!("V{i}").as_bytes());
+            payload.push(CHAR_ENVIRON_VALUE);
+            payload.push(b'x');
         }
 
-        buffer.pop();
+        let mut session = TelnetSession::create(false);
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NEW_ENVIRON]);
+        session.accept_data(&iac_subneg(CHAR_NEW_ENVIRON, &payload));
+        assert_eq!(session.environment().len(), MAX_ENVIRONMENT_VARIABLES);
+
+        /* Known variables are still updated */
+        let payload = [
+            &[CHAR_ENVIRON_INFO, CHAR_ENVIRON_VAR][..],
+            b"V0",
+            &[CHAR_ENVIRON_VALUE],
+        ];
+        session.accept_data(&iac_subneg(CHAR_NEW_ENVIRON, &payload.concat()));
+        assert_eq!(session.environment()["V0"], "");
     }
-}
 
-mod tests {
-    #[allow(unused_imports)]
-    use super::*;
+    /// Builds `IAC SB TTYPE IS <terminal_type> IAC SE`
+    fn build_terminal_type_is(terminal_type: &str) -> Vec<u8> {
+        [
+            &[
+                CHAR_IAC,
+                CHAR_SUB_NEGOTIATION,
+                CHAR_TERMINAL_TYPE,
+                CHAR_TERMINAL_TYPE_IS,
+            ][..],
+            terminal_type.as_bytes(),
+            &[CHAR_IAC, CHAR_SUB_NEGOTIATION_END],
+        ]
+        .concat()
+    }
 
     #[test]
-    fn erase_current_line_should_work() {
-        let mut buffer = vec!['a', 'b', 'c', '\r', '\n', 'd', 'e', 'f'];
-        erase_current_line(&mut buffer);
-        /* RFC-854: The recipient should delete characters from the data stream back to, but
-         * not including, the last "CR LF" sequence sent over the TELNET connection. */
-        assert_eq!(buffer, ['a', 'b', 'c', '\r', '\n']);
+    fn terminal_type_cycle_should_parse_mtts() {
+        let send = [
+            CHAR_IAC,
+            CHAR_SUB_NEGOTIATION,
+            CHAR_TERMINAL_TYPE,
+            CHAR_TERMINAL_TYPE_SEND,
+            CHAR_IAC,
+            CHAR_SUB_NEGOTIATION_END,
+        ];
+        let mut session = TelnetSession::create(false);
+        assert_eq!(
+            session.request_terminal_type(),
+            [CHAR_IAC, CHAR_DO, CHAR_TERMINAL_TYPE]
+        );
 
-        erase_current_line(&mut buffer);
-        assert_eq!(buffer, ['a', 'b', 'c', '\r', '\n']);
+        let response = session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_TYPE]);
+        assert_eq!(response.as_deref(), Some(&send[..]));
 
-        let mut buffer = vec!['a', 'b', 'c', 'd', 'e', 'f'];
-        erase_current_line(&mut buffer);
-        assert!(buffer.is_empty());
+        let response = session.accept_data(&build_terminal_type_is("MUDLET"));
+        assert_eq!(response.as_deref(), Some(&send[..]));
+
+        let response = session.accept_data(&build_terminal_type_is("XTERM-256COLOR"));
+        assert_eq!(response.as_deref(), Some(&send[..]));
+
+        let response = session.accept_data(&build_terminal_type_is("MTTS 15"));
+        assert_eq!(response, None);
+
+        assert_eq!(session.terminal_types(), ["MUDLET", "XTERM-256COLOR"]);
+        let capabilities = session.terminal_capabilities().unwrap();
+        assert!(capabilities.contains(
+            TerminalCapabilities::ANSI
+                | TerminalCapabilities::VT100
+                | TerminalCapabilities::UTF_8
+                | TerminalCapabilities::COLORS_256
+        ));
+        assert!(!capabilities.contains(TerminalCapabilities::MOUSE_TRACKING));
+    }
+
+    #[test]
+    fn terminal_type_cycle_should_end_on_repetition() {
+        let mut session = TelnetSession::create(false);
+
+        /* TTYPE isn't enabled yet, so the terminal type is ignored */
+        assert_eq!(session.accept_data(&build_terminal_type_is("EVIL")), None);
+        assert!(session.terminal_types().is_empty());
+
+        let response = session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_TYPE]);
+        assert!(response.is_some_and(|r| r.starts_with(&[CHAR_IAC, CHAR_DO, CHAR_TERMINAL_TYPE])));
+
+        assert!(session
+            .accept_data(&build_terminal_type_is("VT100"))
+            .is_some());
+        assert_eq!(session.accept_data(&build_terminal_type_is("VT100")), None);
+        assert_eq!(session.terminal_types(), ["VT100"]);
+        assert_eq!(session.terminal_capabilities(), None);
+    }
+
+    #[test]
+    fn terminal_type_cycle_should_end_on_any_repetition() {
+        let mut session = TelnetSession::create(false);
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_TYPE]);
+
+        assert!(session.accept_data(&build_terminal_type_is("A")).is_some());
+        assert!(session.accept_data(&build_terminal_type_is("B")).is_some());
+        assert_eq!(session.accept_data(&build_terminal_type_is("A")), None);
+        assert_eq!(session.terminal_types(), ["A", "B"]);
+    }
+
+    #[test]
+    fn terminal_type_cycle_should_be_limited() {
+        let mut session = TelnetSession::create(false);
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_TYPE]);
+
+        let requests = (0..20)
+            .filter_map(|i| session.accept_data(&build_terminal_type_is(&i.to_string())))
+            .count();
+        assert_eq!(requests, MAX_TERMINAL_TYPES - 1);
+        assert_eq!(session.terminal_types().len(), MAX_TERMINAL_TYPES);
+    }
+
+    #[test]
+    fn window_size_should_be_cleared_on_wont() {
+        let mut session = TelnetSession::create(false);
+        session.set_option_policy(|option, _| option == CHAR_NAWS);
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NAWS]);
+        session.accept_data(&iac_subneg(CHAR_NAWS, &[0, 80, 1, 0]));
+        assert_eq!(session.get_window_size(), Some((80, 256)));
+
+        session.accept_data(&[CHAR_IAC, CHAR_WONT, 31]);
+        assert_eq!(session.get_window_size(), None);
+
+        /* Not expected anymore */
+        session.accept_data(&iac_subneg(CHAR_NAWS, &[0, 80, 0, 24]));
+        assert_eq!(session.get_window_size(), None);
+    }
+
+    #[test]
+    fn server_terminal_type_should_be_sent_on_request() {
+        let mut session = TelnetSession::create(false);
+        session.set_server_terminal_type("TELNET-SERVER");
+
+        let send = iac_subneg(CHAR_TERMINAL_TYPE, &[CHAR_TERMINAL_TYPE_SEND]);
+        assert_eq!(session.accept_data(&send), None);
+
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DONT, CHAR_TERMINAL_TYPE]),
+            None
+        );
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_TERMINAL_TYPE]),
+            Some(vec![CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_TYPE])
+        );
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_TERMINAL_TYPE]),
+            None
+        );
+        assert_eq!(
+            session.accept_data(&send),
+            Some(iac_subneg(CHAR_TERMINAL_TYPE, b"\0TELNET-SERVER"))
+        );
+    }
+
+    #[test]
+    fn x_display_location_should_be_captured() {
+        let mut session = TelnetSession::create(false);
+
+        /* XDISPLOC isn't enabled yet, so the location is ignored */
+        session.accept_data(&iac_subneg(CHAR_X_DISPLAY_LOCATION, b"\0evil:0.0"));
+        assert_eq!(session.x_display_location(), None);
+
+        let response = session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_X_DISPLAY_LOCATION]);
+        assert_eq!(
+            response,
+            Some(
+                [
+                    &[CHAR_IAC, CHAR_DO, CHAR_X_DISPLAY_LOCATION][..],
+                    &iac_subneg(CHAR_X_DISPLAY_LOCATION, &[CHAR_X_DISPLAY_LOCATION_SEND]),
+                ]
+                .concat()
+            )
+        );
+
+        let response = session.accept_data(&iac_subneg(CHAR_X_DISPLAY_LOCATION, b"\0:0.0"));
+        assert_eq!(response, None);
+        assert_eq!(session.x_display_location(), Some(":0.0"));
+
+        session.accept_data(&[CHAR_IAC, CHAR_WONT, CHAR_X_DISPLAY_LOCATION]);
+        session.accept_data(&iac_subneg(CHAR_X_DISPLAY_LOCATION, b"\0:1.0"));
+        assert_eq!(session.x_display_location(), None);
+    }
+
+    #[test]
+    fn client_location_should_be_captured() {
+        let mut session = TelnetSession::create(false);
+        let response = session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_SEND_LOCATION]);
+        assert_eq!(response, Some(vec![CHAR_IAC, CHAR_DO, CHAR_SEND_LOCATION]));
+
+        let response = session.accept_data(&iac_subneg(CHAR_SEND_LOCATION, b"Room 42"));
+        assert_eq!(response, None);
+        assert_eq!(session.client_location(), Some("Room 42"));
+
+        session.accept_data(&[CHAR_IAC, CHAR_WONT, CHAR_SEND_LOCATION]);
+        assert_eq!(session.client_location(), None);
+    }
+
+    #[test]
+    fn terminal_speed_should_be_parsed() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(
+            session.request_terminal_speed(),
+            [CHAR_IAC, CHAR_DO, CHAR_TERMINAL_SPEED]
+        );
+
+        let response = session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_SPEED]);
+        assert_eq!(
+            response,
+            Some(iac_subneg(CHAR_TERMINAL_SPEED, &[CHAR_TERMINAL_SPEED_SEND]))
+        );
+
+        let response = session.accept_data(&iac_subneg(CHAR_TERMINAL_SPEED, b"\x0038400,38400"));
+        assert_eq!(response, None);
+        assert_eq!(session.terminal_speed(), Some((38400, 38400)));
+
+        /* Malformed speeds are ignored */
+        session.accept_data(&iac_subneg(CHAR_TERMINAL_SPEED, b"\0fast,9600"));
+        session.accept_data(&iac_subneg(CHAR_TERMINAL_SPEED, b"\x009600"));
+        assert_eq!(session.terminal_speed(), Some((38400, 38400)));
+
+        session.accept_data(&[CHAR_IAC, CHAR_WONT, CHAR_TERMINAL_SPEED]);
+        assert_eq!(session.terminal_speed(), None);
+
+        /* TSPEED is disabled, so the speed is ignored */
+        session.accept_data(&iac_subneg(CHAR_TERMINAL_SPEED, b"\x009600,9600"));
+        assert_eq!(session.terminal_speed(), None);
+    }
+
+    #[test]
+    fn send_command_should_update_option_state() {
+        let mut session = TelnetSession::create(false);
+
+        assert_eq!(session.send_command(CHAR_DO, CHAR_NAWS), [255, 253, 31]);
+        assert!(session.requested_options.contains_key(&CHAR_NAWS));
+
+        assert_eq!(session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_NAWS]), None);
+        assert!(session.is_remote_option_enabled(CHAR_NAWS));
+
+        assert_eq!(session.send_command(CHAR_WILL, CHAR_ECHO), [255, 251, 1]);
+        assert!(session.is_echoing());
+
+        /* The client agreed to the offer, which needs no reply */
+        assert_eq!(session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO]), None);
+        assert!(session.is_echoing());
+
+        assert_eq!(session.send_command(CHAR_DONT, CHAR_NAWS), [255, 254, 31]);
+        assert!(!session.is_remote_option_enabled(CHAR_NAWS));
+
+        assert_eq!(
+            session.send_subneg(CHAR_TERMINAL_TYPE, &[CHAR_TERMINAL_TYPE_SEND]),
+            [255, 250, 24, 1, 255, 240]
+        );
+    }
+
+    #[test]
+    fn split_telnet_should_separate_text_and_commands() {
+        let data = [
+            b"ab".as_slice(),
+            &[CHAR_IAC, CHAR_DO, CHAR_ECHO],
+            &[
+                CHAR_IAC,
+                CHAR_SUB_NEGOTIATION,
+                CHAR_NAWS,
+                0,
+                80,
+                CHAR_IAC,
+                CHAR_IAC,
+            ],
+            &[CHAR_IAC, CHAR_SUB_NEGOTIATION_END],
+            &[b'c', CHAR_IAC, CHAR_IAC, b'd', CHAR_IAC, CHAR_NOP],
+        ]
+        .concat();
+
+        assert_eq!(
+            split_telnet(&data),
+            [
+                TelnetSegment::Text(b"ab".to_vec()),
+                TelnetSegment::Command(vec![CHAR_IAC, CHAR_DO, CHAR_ECHO]),
+                TelnetSegment::Command(vec![
+                    CHAR_IAC,
+                    CHAR_SUB_NEGOTIATION,
+                    CHAR_NAWS,
+                    0,
+                    80,
+                    CHAR_IAC,
+                    CHAR_IAC,
+                    CHAR_IAC,
+                    CHAR_SUB_NEGOTIATION_END
+                ]),
+                TelnetSegment::Text(vec![b'c', CHAR_IAC, b'd']),
+                TelnetSegment::Command(vec![CHAR_IAC, CHAR_NOP]),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_telnet_should_return_partial_commands() {
+        assert_eq!(
+            split_telnet(&[b'a', CHAR_IAC, CHAR_WILL]),
+            [
+                TelnetSegment::Text(b"a".to_vec()),
+                TelnetSegment::Partial(vec![CHAR_IAC, CHAR_WILL]),
+            ]
+        );
+        assert_eq!(
+            split_telnet(&[CHAR_IAC, CHAR_SUB_NEGOTIATION, CHAR_NAWS, 0, CHAR_IAC]),
+            [TelnetSegment::Partial(vec![
+                CHAR_IAC,
+                CHAR_SUB_NEGOTIATION,
+                CHAR_NAWS,
+                0,
+                CHAR_IAC
+            ])]
+        );
+    }
+
+    #[test]
+    fn split_telnet_should_separate_aborted_commands() {
+        assert_eq!(
+            split_telnet(&[CHAR_IAC, CHAR_WILL, CHAR_IAC, CHAR_NOP, b'a']),
+            [
+                TelnetSegment::Command(vec![CHAR_IAC, CHAR_WILL]),
+                TelnetSegment::Command(vec![CHAR_IAC, CHAR_NOP]),
+                TelnetSegment::Text(b"a".to_vec()),
+            ]
+        );
+        assert_eq!(
+            split_telnet(&[
+                CHAR_IAC,
+                CHAR_SUB_NEGOTIATION,
+                CHAR_NAWS,
+                0,
+                CHAR_IAC,
+                CHAR_DO
+            ]),
+            [
+                TelnetSegment::Command(vec![CHAR_IAC, CHAR_SUB_NEGOTIATION, CHAR_NAWS, 0]),
+                TelnetSegment::Partial(vec![CHAR_IAC, CHAR_DO]),
+            ]
+        );
+    }
+
+    #[test]
+    fn iac_command_should_work() {
+        assert_eq!(iac_command(CHAR_WILL, CHAR_ECHO), [255, 251, 1]);
+        assert_eq!(iac_command(CHAR_DONT, CHAR_LINEMODE), [255, 254, 34]);
+    }
+
+    #[test]
+    fn iac_subneg_should_escape_payload() {
+        assert_eq!(
+            iac_subneg(CHAR_LINEMODE, &[CHAR_LINEMODE_MODE, 3]),
+            [255, 250, 34, 1, 3, 255, 240]
+        );
+        assert_eq!(
+            iac_subneg(99, &[1, CHAR_IAC, 2]),
+            [255, 250, 99, 1, 255, 255, 2, 255, 240]
+        );
+        assert_eq!(iac_subneg(99, &[]), [255, 250, 99, 255, 240]);
+    }
+
+    #[test]
+    fn keepalive_should_be_nop() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(session.keepalive(), [255, 241]);
+    }
+
+    #[test]
+    fn bell_should_be_bel() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(session.bell(), [7]);
+        assert_eq!(session.visual_bell(), b"\x1b[?5h\x1b[?5l");
+    }
+
+    #[test]
+    fn activity_should_be_tracked() {
+        let mut session = TelnetSession::create(false);
+        let idle_since = session.idle_since();
+
+        session.accept_data(&[]);
+        assert_eq!(session.idle_since(), idle_since);
+
+        std::thread::sleep(Duration::from_millis(10));
+        let before = Instant::now();
+        session.accept_data(b"a");
+        let after = Instant::now();
+
+        /* The activity is the accepted data, so it's later than the initial one */
+        assert!(session.idle_since() > idle_since);
+        assert!(before <= session.idle_since() && session.idle_since() <= after);
+    }
+
+    #[test]
+    fn mssp_should_report_variables() {
+        let mut session = TelnetSession::create(false);
+        assert_eq!(
+            session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_MSSP]),
+            Some(vec![CHAR_IAC, CHAR_WONT, CHAR_MSSP])
+        );
+
+        session.set_mssp_var("NAME", "Test");
+        session.set_mssp_var("PLAYERS", "1");
+        session.set_mssp_var("PLAYERS", "2");
+
+        let response = session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_MSSP]);
+        let expected = [
+            &[CHAR_IAC, CHAR_WILL, CHAR_MSSP][..],
+            &[CHAR_IAC, CHAR_SUB_NEGOTIATION, CHAR_MSSP],
+            &[CHAR_MSSP_VAR],
+            b"NAME",
+            &[CHAR_MSSP_VAL],
+            b"Test",
+            &[CHAR_MSSP_VAR],
+            b"PLAYERS",
+            &[CHAR_MSSP_VAL],
+            b"2",
+            &[CHAR_IAC, CHAR_SUB_NEGOTIATION_END],
+        ]
+        .concat();
+        assert_eq!(response, Some(expected));
+    }
+
+    #[cfg(feature = "mccp")]
+    #[test]
+    fn compression_should_work() {
+        use flate2::write::ZlibDecoder;
+
+        let mut session = TelnetSession::create(false);
+        assert_eq!(
+            session.offer_compression(),
+            [CHAR_IAC, CHAR_WILL, CHAR_MCCP2]
+        );
+        assert!(!session.compression_active());
+
+        let response = session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_MCCP2]);
+        assert_eq!(
+            response,
+            Some(vec![
+                CHAR_IAC,
+                CHAR_SUB_NEGOTIATION,
+                CHAR_MCCP2,
+                CHAR_IAC,
+                CHAR_SUB_NEGOTIATION_END
+            ])
+        );
+        assert!(session.compression_active());
+
+        /* Output after the marker has to be valid deflate data */
+        let compressed = session.prepare_output(b"Hello, world!\r\n");
+        assert_ne!(compressed, b"Hello, world!\r\n");
+
+        let mut decoder = ZlibDecoder::new(vec![]);
+        decoder.write_all(&compressed).unwrap();
+        decoder.flush().unwrap();
+        assert_eq!(decoder.get_ref(), b"Hello, world!\r\n");
+    }
+
+    #[cfg(feature = "mccp")]
+    #[test]
+    fn refused_compression_should_finish_stream() {
+        use flate2::write::ZlibDecoder;
+
+        let mut session = TelnetSession::create(false);
+        session.offer_compression();
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_MCCP2]);
+        let compressed = session.prepare_output(b"Hello");
+
+        let response = session
+            .accept_data(&[CHAR_IAC, CHAR_DONT, CHAR_MCCP2])
+            .unwrap();
+        assert!(!session.compression_active());
+
+        /* The stream ends right before the uncompressed reply */
+        let (finished, reply) = response.split_at(response.len() - 3);
+        assert_eq!(reply, [CHAR_IAC, CHAR_WONT, CHAR_MCCP2]);
+
+        let mut decoder = ZlibDecoder::new(vec![]);
+        decoder.write_all(&compressed).unwrap();
+        decoder.write_all(finished).unwrap();
+        assert_eq!(decoder.finish().unwrap(), b"Hello");
+        assert_eq!(session.prepare_output(b"!"), b"!");
     }
 }
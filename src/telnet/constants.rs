@@ -0,0 +1,156 @@
+//! Telnet control bytes, grouped by category. Library consumers can use these to
+//! build or recognize command sequences instead of hardcoding magic numbers.
+//!
+//! # Examples
+//!
+//! ```
+//! use telnet_server::telnet::constants;
+//!
+//! fn describe(byte: u8) -> &'static str {
+//!     match byte {
+//!         constants::CHAR_IAC => "IAC",
+//!         constants::CHAR_WILL => "WILL",
+//!         _ => "data",
+//!     }
+//! }
+//!
+//! assert_eq!(describe(255), "IAC");
+//! assert_eq!(describe(b'a'), "data");
+//! ```
+
+/* Commands, see RFC 854 unless noted otherwise */
+
+/// Command: End of record, marks the end of e.g. a prompt (RFC 885)
+pub const CHAR_EOR: u8 = 239;
+/// Command: End of sub negotiation parameters (SE)
+pub const CHAR_SUB_NEGOTIATION_END: u8 = 240;
+/// Command: No operation (NOP)
+pub const CHAR_NOP: u8 = 241;
+/// Command: Data mark, the data stream portion of a Synch (DM)
+pub const CHAR_DATA_MARK: u8 = 242;
+/// Command: NVT character BRK (break)
+pub const CHAR_BREAK: u8 = 243;
+/// Command: Erase character (EC)
+pub const CHAR_ERASE_CHARACTER: u8 = 247;
+/// Command: Erase line (EL)
+pub const CHAR_ERASE_LINE: u8 = 248;
+/// Command: Go ahead (GA)
+pub const CHAR_GO_AHEAD: u8 = 249;
+/// Command: Start of sub negotiation parameters (SB)
+pub const CHAR_SUB_NEGOTIATION: u8 = 250;
+/// Command: Sender wants to begin or confirms performing an option
+pub const CHAR_WILL: u8 = 251;
+/// Command: Sender refuses to perform or continue performing an option
+pub const CHAR_WONT: u8 = 252;
+/// Command: Sender requests or confirms that the other party performs an option
+pub const CHAR_DO: u8 = 253;
+/// Command: Sender demands that the other party stops performing an option
+pub const CHAR_DONT: u8 = 254;
+/// Command: Interpret as command (IAC), starts every command sequence
+pub const CHAR_IAC: u8 = 255;
+
+/* Options */
+
+/// Option: The sender echoes data it receives (RFC 857)
+pub const CHAR_ECHO: u8 = 1;
+/// Option: The sender doesn't send GO AHEAD commands (RFC 858)
+pub const CHAR_SUPPRESS_GO_AHEAD: u8 = 3;
+/// Option: Timing mark, synchronizes both parties (RFC 860)
+pub const CHAR_TIMING_MARK: u8 = 6;
+/// Option: Send location, the client reports its location (RFC 779)
+pub const CHAR_SEND_LOCATION: u8 = 23;
+/// Option: Terminal type, TTYPE (RFC 1091)
+pub const CHAR_TERMINAL_TYPE: u8 = 24;
+/// Option: End of record, records are terminated by `IAC EOR` (RFC 885)
+pub const CHAR_END_OF_RECORD: u8 = 25;
+/// Option: Negotiate about window size (RFC 1073)
+pub const CHAR_NAWS: u8 = 31;
+/// Option: Terminal speed, the client reports its baud rate (RFC 1079)
+pub const CHAR_TERMINAL_SPEED: u8 = 32;
+/// Option: Remote flow control, LFLOW (RFC 1372)
+pub const CHAR_TOGGLE_FLOW_CONTROL: u8 = 33;
+/// Option: Linemode, the client edits lines locally (RFC 1184)
+pub const CHAR_LINEMODE: u8 = 34;
+/// Option: X display location, the client reports its X display (RFC 1096)
+pub const CHAR_X_DISPLAY_LOCATION: u8 = 35;
+/// Option: Authentication, the client authenticates itself (RFC 2941)
+pub const CHAR_AUTHENTICATION: u8 = 37;
+/// Option: New environment, the client reports environment variables (RFC 1572)
+pub const CHAR_NEW_ENVIRON: u8 = 39;
+/// Option: MUD Server Status Protocol, MSSP (MUD protocol, no RFC)
+pub const CHAR_MSSP: u8 = 70;
+/// Option: MUD Client Compression Protocol v2, MCCP2 (MUD protocol, no RFC)
+pub const CHAR_MCCP2: u8 = 86;
+
+/* Control chars of the network virtual terminal (RFC 854) and ANSI terminals */
+
+/// Control char: Null (NUL), a no-op that follows a bare carriage return (`CR NUL`)
+pub const CHAR_NUL: u8 = 0;
+/// Control char: Bell (BEL)
+pub const CHAR_BEL: u8 = 7;
+/// Control char: Back space (BS)
+pub const CHAR_BACK_SPACE: u8 = 8;
+/// Control char: Horizontal tab (HT)
+pub const CHAR_TAB: u8 = 9;
+/// Control char: XON (DC1), resumes output that was stopped by XOFF
+pub const CHAR_XON: u8 = 17;
+/// Control char: XOFF (DC3), stops output until XON
+pub const CHAR_XOFF: u8 = 19;
+/// Control char: Negative acknowledge (NAK, Ctrl-U), erases the current line in most
+/// terminals
+pub const CHAR_NAK: u8 = 21;
+/// Control char: Escape (ESC), starts ANSI escape sequences
+pub const CHAR_ESCAPE: u8 = 27;
+/// Control char: Delete (DEL)
+pub const CHAR_DELETE: u8 = 127;
+
+/* Sub negotiation parameters */
+
+/// LINEMODE: MODE sub negotiation (RFC 1184)
+pub const CHAR_LINEMODE_MODE: u8 = 1;
+/// LINEMODE: EDIT flag of MODE, the client edits lines locally (RFC 1184)
+pub const LINEMODE_EDIT: u8 = 1;
+/// MSSP: Starts a variable name
+pub const CHAR_MSSP_VAR: u8 = 1;
+/// MSSP: Starts a variable value
+pub const CHAR_MSSP_VAL: u8 = 2;
+/// NEW-ENVIRON: Reply containing the requested variables (RFC 1572)
+pub const CHAR_ENVIRON_IS: u8 = 0;
+/// NEW-ENVIRON: Request for variables (RFC 1572)
+pub const CHAR_ENVIRON_SEND: u8 = 1;
+/// NEW-ENVIRON: Unsolicited update of variables (RFC 1572)
+pub const CHAR_ENVIRON_INFO: u8 = 2;
+/// NEW-ENVIRON: Starts a well-known variable name (RFC 1572)
+pub const CHAR_ENVIRON_VAR: u8 = 0;
+/// NEW-ENVIRON: Starts a variable value (RFC 1572)
+pub const CHAR_ENVIRON_VALUE: u8 = 1;
+/// NEW-ENVIRON: Escapes the next byte within names and values (RFC 1572)
+pub const CHAR_ENVIRON_ESC: u8 = 2;
+/// NEW-ENVIRON: Starts a user defined variable name (RFC 1572)
+pub const CHAR_ENVIRON_USERVAR: u8 = 3;
+/// TTYPE: Reply containing the terminal type (RFC 1091)
+pub const CHAR_TERMINAL_TYPE_IS: u8 = 0;
+/// TTYPE: Request for the (next) terminal type (RFC 1091)
+pub const CHAR_TERMINAL_TYPE_SEND: u8 = 1;
+/// XDISPLOC: Reply containing the X display location (RFC 1096)
+pub const CHAR_X_DISPLAY_LOCATION_IS: u8 = 0;
+/// XDISPLOC: Request for the X display location (RFC 1096)
+pub const CHAR_X_DISPLAY_LOCATION_SEND: u8 = 1;
+/// AUTHENTICATION: Authentication data of the client (RFC 2941)
+pub const CHAR_AUTHENTICATION_IS: u8 = 0;
+/// AUTHENTICATION: Request for authentication, lists the supported types (RFC 2941)
+pub const CHAR_AUTHENTICATION_SEND: u8 = 1;
+/// AUTHENTICATION: Reply of the server to authentication data (RFC 2941)
+pub const CHAR_AUTHENTICATION_REPLY: u8 = 2;
+/// TSPEED: Reply containing the terminal speed (RFC 1079)
+pub const CHAR_TERMINAL_SPEED_IS: u8 = 0;
+/// TSPEED: Request for the terminal speed (RFC 1079)
+pub const CHAR_TERMINAL_SPEED_SEND: u8 = 1;
+/// LFLOW: Disables flow control (RFC 1372)
+pub const CHAR_FLOW_CONTROL_OFF: u8 = 0;
+/// LFLOW: Enables flow control (RFC 1372)
+pub const CHAR_FLOW_CONTROL_ON: u8 = 1;
+/// LFLOW: Any char restarts output that was stopped by XOFF (RFC 1372)
+pub const CHAR_FLOW_CONTROL_RESTART_ANY: u8 = 2;
+/// LFLOW: Only XON restarts output that was stopped by XOFF (RFC 1372)
+pub const CHAR_FLOW_CONTROL_RESTART_XON: u8 = 3;
@@ -0,0 +1,227 @@
+use log::error;
+
+use crate::telnet::constants::CHAR_IAC;
+use crate::telnet::parser::{TelnetParser, TelnetToken};
+use crate::telnet::{iac_subneg, TelnetEvent, TelnetSession};
+
+/// Enumeration of length prefixes that frames of a `FramedSession` start with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthPrefix {
+    /// Two bytes, big endian (network byte order)
+    U16BigEndian,
+    /// Two bytes, little endian
+    U16LittleEndian,
+    /// Four bytes, big endian (network byte order)
+    U32BigEndian,
+    /// Four bytes, little endian
+    U32LittleEndian,
+}
+
+impl LengthPrefix {
+    /// Returns the number of bytes of the prefix
+    fn size(self) -> usize {
+        match self {
+            LengthPrefix::U16BigEndian | LengthPrefix::U16LittleEndian => 2,
+            LengthPrefix::U32BigEndian | LengthPrefix::U32LittleEndian => 4,
+        }
+    }
+
+    /// Decodes the frame length of given `prefix`, which has to be `size` bytes long
+    fn decode(self, prefix: &[u8]) -> usize {
+        match self {
+            LengthPrefix::U16BigEndian => u16::from_be_bytes([prefix[0], prefix[1]]) as usize,
+            LengthPrefix::U16LittleEndian => u16::from_le_bytes([prefix[0], prefix[1]]) as usize,
+            LengthPrefix::U32BigEndian => {
+                u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize
+            }
+            LengthPrefix::U32LittleEndian => {
+                u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize
+            }
+        }
+    }
+}
+
+/// Session for protocols that tunnel length-prefixed binary frames through Telnet.
+/// All data is treated as binary (like with TRANSMIT-BINARY): It isn't echoed or
+/// split into lines, but collected into frames that are emitted as
+/// `TelnetEvent::Frame` once they're complete, even if they're split across reads.
+/// Commands are still handled by the wrapped `TelnetSession`.
+///
+/// Frames longer than the data buffer limit (see `BufferLimits::data`) are
+/// discarded.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::framed::{FramedSession, LengthPrefix};
+/// use telnet_server::telnet::{TelnetEvent, TelnetSession};
+///
+/// let session = TelnetSession::create(false);
+/// let mut framed_session = FramedSession::new(session, LengthPrefix::U16BigEndian);
+/// framed_session.accept_data(&[0, 3, b'a']);
+/// framed_session.accept_data(&[b'b', b'c', 0]);
+///
+/// assert_eq!(framed_session.take_events(), [TelnetEvent::Frame(b"abc".to_vec())]);
+/// ```
+pub struct FramedSession {
+    /// The wrapped session, handling all commands
+    session: TelnetSession,
+    /// Length prefix of every frame
+    prefix: LengthPrefix,
+    /// Splits incoming data into data bytes and commands
+    parser: TelnetParser,
+    /// Buffer for the currently read frame, including its prefix
+    frame: Vec<u8>,
+    /// Number of bytes of a too long frame that still have to be discarded
+    discarded_bytes: usize,
+}
+
+impl FramedSession {
+    /// Creates a new `FramedSession`
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - The session that handles all commands
+    /// * `prefix` - Length prefix of every frame
+    pub fn new(session: TelnetSession, prefix: LengthPrefix) -> FramedSession {
+        FramedSession {
+            session,
+            prefix,
+            parser: TelnetParser::new(),
+            frame: vec![],
+            discarded_bytes: 0,
+        }
+    }
+
+    /// Accepts incoming tcp stream data and maybe returns a response that should be sent
+    /// back to the Telnet client, see `TelnetSession::accept_data`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Incoming TCP stream data
+    pub fn accept_data(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        if !data.is_empty() {
+            self.session.mark_activity();
+        }
+
+        let mut input = data.iter().copied();
+
+        while let Some(token) = self.parser.next_token(&mut input) {
+            match token {
+                TelnetToken::Data(byte) => self.push_frame_byte(byte),
+                TelnetToken::Command(command, option) => {
+                    let sequence: Vec<u8> = [CHAR_IAC, command].into_iter().chain(option).collect();
+                    self.session.receive(&sequence);
+                }
+                TelnetToken::SubNegotiation(option, payload) => {
+                    self.session.receive(&iac_subneg(option, &payload));
+                }
+            }
+        }
+
+        let response = self.session.take_output();
+
+        if !response.is_empty() {
+            Some(response)
+        } else {
+            None
+        }
+    }
+
+    /// Returns and removes all events that occurred since the last call, including the
+    /// completed frames. See `TelnetSession::take_events`.
+    pub fn take_events(&mut self) -> Vec<TelnetEvent> {
+        self.session.take_events()
+    }
+
+    /// Returns the wrapped session, e.g. to check negotiated options
+    pub fn session(&self) -> &TelnetSession {
+        &self.session
+    }
+
+    /// Returns the wrapped session mutably, e.g. to send commands
+    pub fn session_mut(&mut self) -> &mut TelnetSession {
+        &mut self.session
+    }
+
+    /// Adds given data byte to the current frame, emitting the frame once it's complete
+    ///
+    /// # Arguments
+    ///
+    /// * `byte` - The next data byte
+    fn push_frame_byte(&mut self, byte: u8) {
+        if self.discarded_bytes > 0 {
+            self.discarded_bytes -= 1;
+            return;
+        }
+
+        self.frame.push(byte);
+
+        let prefix_size = self.prefix.size();
+        if self.frame.len() < prefix_size {
+            return;
+        }
+
+        let length = self.prefix.decode(&self.frame[..prefix_size]);
+
+        if length > self.session.buffer_limits.data {
+            error!("Frame of {length} bytes exceeds the buffer limit, discarding it");
+            self.frame.clear();
+            self.discarded_bytes = length;
+            return;
+        }
+
+        if self.frame.len() == prefix_size + length {
+            let frame = self.frame.split_off(prefix_size);
+            self.frame.clear();
+            self.session.push_event(TelnetEvent::Frame(frame));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telnet::constants::{CHAR_DO, CHAR_ECHO, CHAR_WILL};
+
+    #[test]
+    fn split_frame_should_be_emitted_once() {
+        let mut framed_session =
+            FramedSession::new(TelnetSession::create(false), LengthPrefix::U16BigEndian);
+
+        assert_eq!(framed_session.accept_data(&[0, 4, 1, CHAR_IAC]), None);
+        assert!(framed_session.take_events().is_empty());
+
+        assert_eq!(framed_session.accept_data(&[CHAR_IAC, 2, 3, 0]), None);
+        assert_eq!(
+            framed_session.take_events(),
+            [TelnetEvent::Frame(vec![1, CHAR_IAC, 2, 3])]
+        );
+    }
+
+    #[test]
+    fn commands_should_be_handled_between_frames() {
+        let mut framed_session =
+            FramedSession::new(TelnetSession::create(false), LengthPrefix::U32LittleEndian);
+
+        let response = framed_session.accept_data(&[1, 0, 0, 0, CHAR_IAC, CHAR_DO, CHAR_ECHO, 9]);
+        assert_eq!(response, Some(vec![CHAR_IAC, CHAR_WILL, CHAR_ECHO]));
+
+        let events = framed_session.take_events();
+        assert_eq!(events.last(), Some(&TelnetEvent::Frame(vec![9])));
+    }
+
+    #[test]
+    fn too_long_frame_should_be_discarded() {
+        let mut session = TelnetSession::create(false);
+        session.set_buffer_limits(crate::telnet::BufferLimits {
+            data: 2,
+            ..Default::default()
+        });
+
+        let mut framed_session = FramedSession::new(session, LengthPrefix::U16LittleEndian);
+        framed_session.accept_data(&[3, 0, 1, 2, 3, 1, 0, 4]);
+
+        assert_eq!(framed_session.take_events(), [TelnetEvent::Frame(vec![4])]);
+    }
+}
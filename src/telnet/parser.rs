@@ -0,0 +1,399 @@
+use log::error;
+
+use crate::telnet::constants::*;
+use crate::telnet::{BufferLimits, ParserState, ProtocolError};
+
+/// Token of a Telnet data stream, as produced by `TelnetParser`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TelnetToken {
+    /// A single data byte. Escaped bytes (`IAC IAC`) are already unescaped.
+    Data(u8),
+    /// A command and its option, if it's a negotiation (e.g. `IAC WILL ECHO`)
+    Command(u8, Option<u8>),
+    /// A complete sub negotiation with its option and unescaped payload
+    SubNegotiation(u8, Vec<u8>),
+}
+
+/// Enumeration of states that the `TelnetParser` may have.
+enum State {
+    /// Incoming, non-command data (e.g. text)
+    Data,
+    /// Incoming command data (after IAC)
+    Command,
+    /// Incoming option of given negotiation command (WILL, WONT, DO, DONT)
+    Negotiation(u8),
+    /// Incoming sub negotiation data
+    SubNegotiation,
+    /// Incoming IAC within sub negotiation data
+    SubNegotiationIac,
+}
+
+/// Streaming parser that splits a Telnet data stream into tokens without buffering
+/// the whole stream. Only incomplete sub negotiations are buffered. The parser keeps
+/// its state between calls, so tokens may be split across multiple inputs. This is the
+/// parser of every `TelnetSession`, which handles the tokens.
+///
+/// Protocol errors of the client are recovered from like within a session: A
+/// negotiation without option or a sub negotiation that's interrupted by another
+/// command is discarded, and the `IAC` starts a new command. Oversized sub negotiations
+/// are discarded as well. See `take_error`.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::parser::{TelnetParser, TelnetToken};
+///
+/// let mut parser = TelnetParser::new();
+/// let mut input = [b'a', 255, 253, 1].into_iter();
+///
+/// assert_eq!(parser.next_token(&mut input), Some(TelnetToken::Data(b'a')));
+/// assert_eq!(
+///     parser.next_token(&mut input),
+///     Some(TelnetToken::Command(253, Some(1)))
+/// );
+/// assert_eq!(parser.next_token(&mut input), None);
+/// ```
+pub struct TelnetParser {
+    /// Current state of the parser
+    state: State,
+    /// Buffer for currently read sub negotiation data (option and payload)
+    sub_negotiation: Vec<u8>,
+    /// Maximum number of payload bytes of a sub negotiation, see
+    /// `set_sub_negotiation_limit`
+    sub_negotiation_limit: usize,
+    /// Whether the current sub negotiation exceeded the limit, so it's discarded
+    is_sub_negotiation_truncated: bool,
+    /// Protocol error of the client that occurred during the last update, see
+    /// `take_error`
+    error: Option<ProtocolError>,
+}
+
+impl TelnetParser {
+    /// Creates a new `TelnetParser`. Sub negotiations are limited like by default
+    /// within a session, see `BufferLimits::sub_negotiation`.
+    pub fn new() -> TelnetParser {
+        TelnetParser {
+            state: State::Data,
+            sub_negotiation: vec![],
+            sub_negotiation_limit: BufferLimits::default().sub_negotiation,
+            is_sub_negotiation_truncated: false,
+            error: None,
+        }
+    }
+
+    /// Sets the maximum number of payload bytes of a single sub negotiation. Longer sub
+    /// negotiations are discarded completely.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The maximum number of payload bytes
+    pub fn set_sub_negotiation_limit(&mut self, limit: usize) {
+        self.sub_negotiation_limit = limit;
+    }
+
+    /// Returns the protocol error of the client that occurred while parsing the last
+    /// byte, removing it. The erroneous sequence was already discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use telnet_server::telnet::parser::TelnetParser;
+    /// use telnet_server::telnet::ProtocolError;
+    ///
+    /// let mut parser = TelnetParser::new();
+    /// parser.update(255); // IAC
+    /// parser.update(251); // WILL
+    /// parser.update(255); // IAC instead of an option
+    ///
+    /// assert_eq!(parser.take_error(), Some(ProtocolError::IncompleteNegotiation));
+    /// assert_eq!(parser.take_error(), None);
+    /// ```
+    pub fn take_error(&mut self) -> Option<ProtocolError> {
+        self.error.take()
+    }
+
+    /// Returns the current state, see `TelnetSession::state`. Data is `ParserState::Idle`.
+    pub(crate) fn state(&self) -> ParserState {
+        match self.state {
+            State::Data => ParserState::Idle,
+            State::Command => ParserState::Command,
+            State::Negotiation(CHAR_WILL) => ParserState::CommandWill,
+            State::Negotiation(CHAR_WONT) => ParserState::CommandWont,
+            State::Negotiation(CHAR_DO) => ParserState::CommandDo,
+            State::Negotiation(_) => ParserState::CommandDont,
+            State::SubNegotiation => ParserState::SubNegotiation,
+            State::SubNegotiationIac => ParserState::SubNegotiationIac,
+        }
+    }
+
+    /// Returns the number of bytes of the partial command, if any. Sub negotiation
+    /// parameters are counted unescaped.
+    pub(crate) fn pending_bytes(&self) -> usize {
+        match self.state {
+            State::Data => 0,
+            State::Command => 1,
+            State::Negotiation(_) => 2,
+            State::SubNegotiation => 2 + self.sub_negotiation.len(),
+            State::SubNegotiationIac => 3 + self.sub_negotiation.len(),
+        }
+    }
+
+    /// Consumes bytes from `input` until the next token is complete
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The incoming bytes
+    ///
+    /// # Returns
+    ///
+    /// The next token or `None` if `input` is exhausted before a token is complete.
+    /// In that case, the consumed bytes are kept and the token is completed by
+    /// following calls.
+    pub fn next_token(&mut self, input: &mut impl Iterator<Item = u8>) -> Option<TelnetToken> {
+        for next in input {
+            if let Some(token) = self.update(next) {
+                return Some(token);
+            }
+        }
+
+        None
+    }
+
+    /// Updates the parser state based on `next` incoming byte
+    ///
+    /// # Arguments
+    ///
+    /// * `next` - The next incoming byte
+    ///
+    /// # Returns
+    ///
+    /// The token that was completed by `next`, if any.
+    pub fn update(&mut self, next: u8) -> Option<TelnetToken> {
+        match self.state {
+            State::Data => {
+                if next == CHAR_IAC {
+                    self.state = State::Command;
+                    return None;
+                }
+
+                Some(TelnetToken::Data(next))
+            }
+            State::Command => {
+                self.state = State::Data;
+
+                match next {
+                    CHAR_IAC => Some(TelnetToken::Data(next)),
+                    CHAR_WILL | CHAR_WONT | CHAR_DO | CHAR_DONT => {
+                        self.state = State::Negotiation(next);
+                        None
+                    }
+                    CHAR_SUB_NEGOTIATION => {
+                        self.state = State::SubNegotiation;
+                        self.sub_negotiation.clear();
+                        self.is_sub_negotiation_truncated = false;
+                        None
+                    }
+                    _ => Some(TelnetToken::Command(next, None)),
+                }
+            }
+            State::Negotiation(_) if next == CHAR_IAC => {
+                /* The option is missing, so the IAC starts a new command instead and the
+                 * following bytes aren't mistaken for data. Option 255 (EXOPL) is not
+                 * supported anyway. */
+                error!("Incomplete negotiation, discarding it");
+                self.error = Some(ProtocolError::IncompleteNegotiation);
+                self.state = State::Command;
+                None
+            }
+            State::Negotiation(command) => {
+                self.state = State::Data;
+                Some(TelnetToken::Command(command, Some(next)))
+            }
+            State::SubNegotiation => {
+                if next == CHAR_IAC {
+                    self.state = State::SubNegotiationIac;
+                } else {
+                    self.push_sub_negotiation(next);
+                }
+
+                None
+            }
+            State::SubNegotiationIac => match next {
+                CHAR_SUB_NEGOTIATION_END => {
+                    self.state = State::Data;
+                    let sub_negotiation = std::mem::take(&mut self.sub_negotiation);
+
+                    if self.is_sub_negotiation_truncated {
+                        let option = sub_negotiation.first().copied();
+                        error!("Dropped oversized sub negotiation: {option:?}");
+                        self.error = Some(ProtocolError::OversizedSubNegotiation { option });
+                        return None;
+                    }
+
+                    sub_negotiation.split_first().map(|(&option, payload)| {
+                        TelnetToken::SubNegotiation(option, payload.to_vec())
+                    })
+                }
+                CHAR_IAC => {
+                    /* IAC IAC is a literal 255 within the sub negotiation data */
+                    self.state = State::SubNegotiation;
+                    self.push_sub_negotiation(next);
+                    None
+                }
+                _ => {
+                    /* Not a valid sequence within sub negotiation data, so the client
+                     * most likely never finishes it. Abort it and treat the IAC as the
+                     * start of a new command instead, so its bytes aren't mistaken for
+                     * data. */
+                    let option = self.sub_negotiation.first().copied();
+                    error!("Aborted sub negotiation by stray command: {option:?}");
+                    self.error = Some(ProtocolError::AbortedSubNegotiation { option });
+                    self.sub_negotiation.clear();
+                    self.state = State::Command;
+                    self.update(next)
+                }
+            },
+        }
+    }
+}
+
+impl TelnetParser {
+    /// Appends a byte to the sub negotiation buffer, discarding what exceeds its limit.
+    /// The option byte doesn't count towards the limit.
+    fn push_sub_negotiation(&mut self, byte: u8) {
+        if self.sub_negotiation.len() > self.sub_negotiation_limit {
+            self.is_sub_negotiation_truncated = true;
+            return;
+        }
+
+        self.sub_negotiation.push(byte);
+    }
+}
+
+impl Default for TelnetParser {
+    fn default() -> Self {
+        TelnetParser::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telnet::iac_subneg;
+
+    fn parse_all(parser: &mut TelnetParser, data: &[u8]) -> Vec<TelnetToken> {
+        let mut input = data.iter().copied();
+        std::iter::from_fn(|| parser.next_token(&mut input)).collect()
+    }
+
+    #[test]
+    fn parser_should_tokenize_mixed_stream() {
+        let mut parser = TelnetParser::new();
+        let data = [
+            &b"hi"[..],
+            &[CHAR_IAC, CHAR_DO, CHAR_ECHO],
+            &[CHAR_IAC, CHAR_IAC],
+            &[CHAR_IAC, CHAR_NOP],
+            &[CHAR_IAC, CHAR_SUB_NEGOTIATION, CHAR_TERMINAL_TYPE],
+            &[CHAR_TERMINAL_TYPE_IS],
+            b"VT",
+            &[CHAR_IAC, CHAR_IAC],
+            &[CHAR_IAC, CHAR_SUB_NEGOTIATION_END],
+            b"!",
+        ]
+        .concat();
+
+        assert_eq!(
+            parse_all(&mut parser, &data),
+            [
+                TelnetToken::Data(b'h'),
+                TelnetToken::Data(b'i'),
+                TelnetToken::Command(CHAR_DO, Some(CHAR_ECHO)),
+                TelnetToken::Data(CHAR_IAC),
+                TelnetToken::Command(CHAR_NOP, None),
+                TelnetToken::SubNegotiation(
+                    CHAR_TERMINAL_TYPE,
+                    vec![CHAR_TERMINAL_TYPE_IS, b'V', b'T', CHAR_IAC]
+                ),
+                TelnetToken::Data(b'!'),
+            ]
+        );
+    }
+
+    #[test]
+    fn parser_should_abort_sub_negotiation_on_stray_command() {
+        let mut parser = TelnetParser::new();
+        let data = [
+            CHAR_IAC,
+            CHAR_SUB_NEGOTIATION,
+            CHAR_TERMINAL_TYPE,
+            CHAR_TERMINAL_TYPE_SEND,
+            CHAR_IAC,
+            CHAR_WILL,
+            CHAR_ECHO,
+            b'a',
+        ];
+
+        assert_eq!(
+            parse_all(&mut parser, &data),
+            [
+                TelnetToken::Command(CHAR_WILL, Some(CHAR_ECHO)),
+                TelnetToken::Data(b'a'),
+            ]
+        );
+        assert_eq!(
+            parser.take_error(),
+            Some(ProtocolError::AbortedSubNegotiation {
+                option: Some(CHAR_TERMINAL_TYPE)
+            })
+        );
+    }
+
+    #[test]
+    fn parser_should_report_protocol_errors() {
+        let mut parser = TelnetParser::new();
+        parse_all(&mut parser, &[CHAR_IAC, CHAR_WILL]);
+        assert_eq!(parser.take_error(), None);
+
+        /* The IAC of an incomplete negotiation starts a new command */
+        assert_eq!(
+            parse_all(&mut parser, &[CHAR_IAC, CHAR_NOP]),
+            [TelnetToken::Command(CHAR_NOP, None)]
+        );
+        assert_eq!(
+            parser.take_error(),
+            Some(ProtocolError::IncompleteNegotiation)
+        );
+
+        parser.set_sub_negotiation_limit(1);
+        let data = iac_subneg(CHAR_NAWS, &[0, 80]);
+        assert_eq!(parse_all(&mut parser, &data), []);
+        assert_eq!(
+            parser.take_error(),
+            Some(ProtocolError::OversizedSubNegotiation {
+                option: Some(CHAR_NAWS)
+            })
+        );
+    }
+
+    #[test]
+    fn parser_should_complete_tokens_across_inputs() {
+        let mut parser = TelnetParser::new();
+        assert_eq!(
+            parse_all(&mut parser, &[b'a', CHAR_IAC]),
+            [TelnetToken::Data(b'a')]
+        );
+        assert_eq!(parse_all(&mut parser, &[CHAR_WILL]), []);
+        assert_eq!(
+            parse_all(
+                &mut parser,
+                &[CHAR_ECHO, CHAR_IAC, CHAR_SUB_NEGOTIATION, 99]
+            ),
+            [TelnetToken::Command(CHAR_WILL, Some(CHAR_ECHO))]
+        );
+        assert_eq!(
+            parse_all(&mut parser, &[1, CHAR_IAC, CHAR_SUB_NEGOTIATION_END]),
+            [TelnetToken::SubNegotiation(99, vec![1])]
+        );
+    }
+}
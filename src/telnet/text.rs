@@ -0,0 +1,411 @@
+//! Helpers to emit styled text via ANSI SGR (Select Graphic Rendition) escape sequences
+//! (`ESC [ ... m`), which most Telnet clients support.
+//!
+//! # Examples
+//!
+//! ```
+//! use telnet_server::telnet::text::{styled, AnsiStyle, Color};
+//!
+//! let warning = styled("Warning!", AnsiStyle::new().fg(Color::Yellow).bold());
+//! assert_eq!(warning, b"\x1b[1;33mWarning!\x1b[0m");
+//! ```
+
+use crate::telnet::constants::CHAR_ESCAPE;
+
+/// The 16 colors of ANSI terminals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+}
+
+impl Color {
+    /// Returns the color of given SGR foreground parameter (`30`-`37`, `90`-`97`)
+    fn from_foreground_parameter(parameter: u16) -> Option<Color> {
+        let colors = [
+            Color::Black,
+            Color::Red,
+            Color::Green,
+            Color::Yellow,
+            Color::Blue,
+            Color::Magenta,
+            Color::Cyan,
+            Color::White,
+            Color::BrightBlack,
+            Color::BrightRed,
+            Color::BrightGreen,
+            Color::BrightYellow,
+            Color::BrightBlue,
+            Color::BrightMagenta,
+            Color::BrightCyan,
+            Color::BrightWhite,
+        ];
+
+        match parameter {
+            30..=37 => Some(colors[usize::from(parameter - 30)]),
+            90..=97 => Some(colors[usize::from(parameter - 90 + 8)]),
+            _ => None,
+        }
+    }
+
+    /// Returns the SGR parameter of this color as foreground color. Background colors
+    /// are offset by 10.
+    fn foreground_parameter(self) -> u8 {
+        match self {
+            Color::Black => 30,
+            Color::Red => 31,
+            Color::Green => 32,
+            Color::Yellow => 33,
+            Color::Blue => 34,
+            Color::Magenta => 35,
+            Color::Cyan => 36,
+            Color::White => 37,
+            Color::BrightBlack => 90,
+            Color::BrightRed => 91,
+            Color::BrightGreen => 92,
+            Color::BrightYellow => 93,
+            Color::BrightBlue => 94,
+            Color::BrightMagenta => 95,
+            Color::BrightCyan => 96,
+            Color::BrightWhite => 97,
+        }
+    }
+}
+
+/// A color of the 16 color palette, the 256 color palette or a truecolor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteColor {
+    /// Color of the 16 color palette, e.g. `31`
+    Basic(Color),
+    /// Color of the 256 color palette, e.g. `38;5;208`
+    Indexed(u8),
+    /// Truecolor with red, green and blue components, e.g. `38;2;10;20;30`
+    Rgb(u8, u8, u8),
+}
+
+impl PaletteColor {
+    /// Returns the SGR parameters of this color
+    ///
+    /// # Arguments
+    ///
+    /// * `is_background` - Whether the parameters should set the background color
+    fn parameters(self, is_background: bool) -> String {
+        match (self, is_background) {
+            (PaletteColor::Basic(color), false) => color.foreground_parameter().to_string(),
+            (PaletteColor::Basic(color), true) => (color.foreground_parameter() + 10).to_string(),
+            (PaletteColor::Indexed(index), false) => format!("38;5;{index}"),
+            (PaletteColor::Indexed(index), true) => format!("48;5;{index}"),
+            (PaletteColor::Rgb(r, g, b), false) => format!("38;2;{r};{g};{b}"),
+            (PaletteColor::Rgb(r, g, b), true) => format!("48;2;{r};{g};{b}"),
+        }
+    }
+}
+
+/// Builder of an SGR escape sequence, e.g. `ESC [ 1 ; 31 m` for bold red text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AnsiStyle {
+    is_reset: bool,
+    is_bold: bool,
+    is_underlined: bool,
+    foreground: Option<PaletteColor>,
+    background: Option<PaletteColor>,
+}
+
+impl AnsiStyle {
+    /// Creates a new `AnsiStyle` without any attributes
+    pub fn new() -> AnsiStyle {
+        AnsiStyle::default()
+    }
+
+    /// Resets all attributes (SGR 0) before applying the other ones
+    pub fn reset(mut self) -> AnsiStyle {
+        self.is_reset = true;
+        self
+    }
+
+    /// Sets bold text (SGR 1)
+    pub fn bold(mut self) -> AnsiStyle {
+        self.is_bold = true;
+        self
+    }
+
+    /// Sets underlined text (SGR 4)
+    pub fn underline(mut self) -> AnsiStyle {
+        self.is_underlined = true;
+        self
+    }
+
+    /// Sets the foreground color of the 16 color palette
+    pub fn fg(mut self, color: Color) -> AnsiStyle {
+        self.foreground = Some(PaletteColor::Basic(color));
+        self
+    }
+
+    /// Sets the background color of the 16 color palette
+    pub fn bg(mut self, color: Color) -> AnsiStyle {
+        self.background = Some(PaletteColor::Basic(color));
+        self
+    }
+
+    /// Sets the foreground color of the 256 color palette
+    pub fn fg_256(mut self, index: u8) -> AnsiStyle {
+        self.foreground = Some(PaletteColor::Indexed(index));
+        self
+    }
+
+    /// Sets the background color of the 256 color palette
+    pub fn bg_256(mut self, index: u8) -> AnsiStyle {
+        self.background = Some(PaletteColor::Indexed(index));
+        self
+    }
+
+    /// Returns the escape sequence of this style. A style without attributes is
+    /// rendered as reset (`ESC [ 0 m`).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut parameters = vec![];
+
+        if self.is_reset {
+            parameters.push("0".to_string());
+        }
+        if self.is_bold {
+            parameters.push("1".to_string());
+        }
+        if self.is_underlined {
+            parameters.push("4".to_string());
+        }
+        if let Some(color) = self.foreground {
+            parameters.push(color.parameters(false));
+        }
+        if let Some(color) = self.background {
+            parameters.push(color.parameters(true));
+        }
+        if parameters.is_empty() {
+            parameters.push("0".to_string());
+        }
+
+        [
+            &[CHAR_ESCAPE, b'['][..],
+            parameters.join(";").as_bytes(),
+            b"m",
+        ]
+        .concat()
+    }
+}
+
+/// Attribute that's set by an SGR escape sequence, see `parse_sgr`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgrAttribute {
+    /// Resets all attributes (`0`)
+    Reset,
+    /// Bold text (`1`)
+    Bold,
+    /// Faint text (`2`)
+    Faint,
+    /// Italic text (`3`)
+    Italic,
+    /// Underlined text (`4`)
+    Underline,
+    /// Blinking text (`5`)
+    Blink,
+    /// Swapped foreground and background colors (`7`)
+    Reverse,
+    /// Neither bold nor faint text (`22`)
+    NormalIntensity,
+    /// Foreground color (`30`-`37`, `90`-`97`, `38;5;n`, `38;2;r;g;b`)
+    Foreground(PaletteColor),
+    /// Background color (`40`-`47`, `100`-`107`, `48;5;n`, `48;2;r;g;b`)
+    Background(PaletteColor),
+    /// Default foreground color (`39`)
+    DefaultForeground,
+    /// Default background color (`49`)
+    DefaultBackground,
+    /// Any other parameter
+    Unknown(u16),
+}
+
+/// Parses the parameters of an SGR escape sequence into attributes
+///
+/// # Arguments
+///
+/// * `params` - The parameters between `ESC [` and `m`, e.g. `1;31`. Empty parameters
+///   are treated as `0`.
+///
+/// # Returns
+///
+/// The attributes in order of the parameters. Parsing stops at invalid parameters and
+/// incomplete extended colors.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::text::{parse_sgr, Color, PaletteColor, SgrAttribute};
+///
+/// assert_eq!(
+///     parse_sgr(b"1;31"),
+///     [
+///         SgrAttribute::Bold,
+///         SgrAttribute::Foreground(PaletteColor::Basic(Color::Red))
+///     ]
+/// );
+/// ```
+pub fn parse_sgr(params: &[u8]) -> Vec<SgrAttribute> {
+    let mut parameters = params.split(|&c| c == b';').map(|parameter| {
+        if parameter.is_empty() {
+            return Some(0);
+        }
+
+        std::str::from_utf8(parameter).ok()?.parse::<u16>().ok()
+    });
+    let mut attributes = vec![];
+
+    while let Some(Some(parameter)) = parameters.next() {
+        let attribute = match parameter {
+            0 => SgrAttribute::Reset,
+            1 => SgrAttribute::Bold,
+            2 => SgrAttribute::Faint,
+            3 => SgrAttribute::Italic,
+            4 => SgrAttribute::Underline,
+            5 => SgrAttribute::Blink,
+            7 => SgrAttribute::Reverse,
+            22 => SgrAttribute::NormalIntensity,
+            39 => SgrAttribute::DefaultForeground,
+            49 => SgrAttribute::DefaultBackground,
+            38 | 48 => {
+                let Some(color) = parse_extended_color(&mut parameters) else {
+                    break;
+                };
+
+                if parameter == 38 {
+                    SgrAttribute::Foreground(color)
+                } else {
+                    SgrAttribute::Background(color)
+                }
+            }
+            _ => match (
+                Color::from_foreground_parameter(parameter),
+                Color::from_foreground_parameter(parameter.wrapping_sub(10)),
+            ) {
+                (Some(color), _) => SgrAttribute::Foreground(PaletteColor::Basic(color)),
+                (_, Some(color)) => SgrAttribute::Background(PaletteColor::Basic(color)),
+                _ => SgrAttribute::Unknown(parameter),
+            },
+        };
+
+        attributes.push(attribute);
+    }
+
+    attributes
+}
+
+/// Parses the color of an extended color parameter (`38` or `48`), i.e. `5;n` or
+/// `2;r;g;b`
+///
+/// # Arguments
+///
+/// * `parameters` - The parameters following the extended color parameter
+///
+/// # Returns
+///
+/// The color or `None` if the parameters are invalid or incomplete.
+fn parse_extended_color(
+    parameters: &mut impl Iterator<Item = Option<u16>>,
+) -> Option<PaletteColor> {
+    let mut component = || u8::try_from(parameters.next()??).ok();
+
+    match component()? {
+        5 => Some(PaletteColor::Indexed(component()?)),
+        2 => Some(PaletteColor::Rgb(component()?, component()?, component()?)),
+        _ => None,
+    }
+}
+
+/// Returns given `text` in given `style`, followed by a reset of all attributes
+///
+/// # Arguments
+///
+/// * `text` - The text that should be styled
+/// * `style` - The style of the text
+pub fn styled(text: &str, style: AnsiStyle) -> Vec<u8> {
+    [
+        style.to_bytes().as_slice(),
+        text.as_bytes(),
+        &AnsiStyle::new().reset().to_bytes(),
+    ]
+    .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn style_should_render_attributes() {
+        assert_eq!(
+            AnsiStyle::new().fg(Color::Red).bold().to_bytes(),
+            b"\x1b[1;31m"
+        );
+        assert_eq!(AnsiStyle::new().reset().to_bytes(), b"\x1b[0m");
+        assert_eq!(AnsiStyle::new().to_bytes(), b"\x1b[0m");
+        assert_eq!(
+            AnsiStyle::new()
+                .underline()
+                .fg(Color::BrightCyan)
+                .bg(Color::Blue)
+                .to_bytes(),
+            b"\x1b[4;96;44m"
+        );
+        assert_eq!(
+            AnsiStyle::new().fg_256(208).bg_256(0).to_bytes(),
+            b"\x1b[38;5;208;48;5;0m"
+        );
+    }
+
+    #[test]
+    fn styled_should_reset_afterwards() {
+        assert_eq!(
+            styled("hi", AnsiStyle::new().bg(Color::BrightWhite)),
+            b"\x1b[107mhi\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn parse_sgr_should_decode_attributes() {
+        assert_eq!(parse_sgr(b"0"), [SgrAttribute::Reset]);
+        assert_eq!(parse_sgr(b""), [SgrAttribute::Reset]);
+        assert_eq!(
+            parse_sgr(b"1;31"),
+            [
+                SgrAttribute::Bold,
+                SgrAttribute::Foreground(PaletteColor::Basic(Color::Red))
+            ]
+        );
+        assert_eq!(
+            parse_sgr(b"38;5;208"),
+            [SgrAttribute::Foreground(PaletteColor::Indexed(208))]
+        );
+        assert_eq!(
+            parse_sgr(b"38;2;10;20;30"),
+            [SgrAttribute::Foreground(PaletteColor::Rgb(10, 20, 30))]
+        );
+        assert_eq!(
+            parse_sgr(b"107;48;5"),
+            [SgrAttribute::Background(PaletteColor::Basic(
+                Color::BrightWhite
+            ))]
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use crate::telnet::{parse_description, TelnetEvent, TelnetSession};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// Synchronous driver that reduces boilerplate when testing a `TelnetSession`.
+/// It feeds data into the session and records its responses and events.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::TelnetSession;
+/// use telnet_server::test_util::TelnetTestDriver;
+///
+/// let mut driver = TelnetTestDriver::new(TelnetSession::create(false));
+/// driver.feed(&[255, 253, 1]).feed_str("hello");
+///
+/// assert_eq!(driver.data_string(), "hello");
+/// assert_eq!(driver.events().len(), 1);
+/// ```
+pub struct TelnetTestDriver {
+    /// The driven session
+    session: TelnetSession,
+    /// Response of the last `feed` call
+    last_response: Option<Vec<u8>>,
+    /// All events that occurred since the driver was created
+    events: Vec<TelnetEvent>,
+}
+
+impl TelnetTestDriver {
+    /// Creates a new `TelnetTestDriver` that drives given `session`
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - The session that should be driven
+    pub fn new(session: TelnetSession) -> TelnetTestDriver {
+        TelnetTestDriver {
+            session,
+            last_response: None,
+            events: vec![],
+        }
+    }
+
+    /// Feeds given `data` into the session, recording its response and events
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Incoming data, as if sent by the client
+    pub fn feed(&mut self, data: &[u8]) -> &mut TelnetTestDriver {
+        self.last_response = self.session.accept_data(data);
+        self.events.extend(self.session.take_events());
+        self
+    }
+
+    /// Feeds given `text` into the session, recording its response and events
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - Incoming text, as if sent by the client
+    pub fn feed_str(&mut self, text: &str) -> &mut TelnetTestDriver {
+        self.feed(text.as_bytes())
+    }
+
+    /// Returns the response of the last `feed` call
+    pub fn last_response(&self) -> Option<&[u8]> {
+        self.last_response.as_deref()
+    }
+
+    /// Returns the current data buffer of the session as `String`
+    pub fn data_string(&self) -> String {
+        self.session.get_data_buffer().iter().collect()
+    }
+
+    /// Returns all events that occurred since the driver was created
+    pub fn events(&self) -> &[TelnetEvent] {
+        &self.events
+    }
+
+    /// Returns the driven session
+    pub fn session(&mut self) -> &mut TelnetSession {
+        &mut self.session
+    }
+}
+
+/// In-memory duplex stream, e.g. to run a `TcpStreamHandler` via `run_handler_over`
+/// without a socket. Reading returns the given input and ends once it's consumed,
+/// like a client that closed its connection. Written data is recorded.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use telnet_server::test_util::MemoryTransport;
+///
+/// let mut transport = MemoryTransport::new(b"hi");
+/// let mut input = vec![];
+/// transport.read_to_end(&mut input).unwrap();
+/// transport.write_all(b"ho").unwrap();
+///
+/// assert_eq!(input, b"hi");
+/// assert_eq!(transport.output(), b"ho");
+/// ```
+pub struct MemoryTransport {
+    /// Data that's not read yet
+    input: VecDeque<u8>,
+    /// All data that was written
+    output: Vec<u8>,
+}
+
+impl MemoryTransport {
+    /// Creates a new `MemoryTransport` that reads given `input`
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - Incoming data, as if sent by the client
+    pub fn new(input: &[u8]) -> MemoryTransport {
+        MemoryTransport {
+            input: input.iter().copied().collect(),
+            output: vec![],
+        }
+    }
+
+    /// Returns all data that was written so far
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl Read for MemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// In-memory trace of a `TelnetSession` that can be shared, so it's still readable
+/// after being passed to `TelnetSession::set_trace`. The trace consists of lines like
+/// `0.000012 < IAC DO ECHO "hi"`: The seconds since the trace started, the direction
+/// (`<` for incoming, `>` for outgoing) and the data, see `describe_command`.
+///
+/// # Examples
+///
+/// ```
+/// use telnet_server::telnet::TelnetSession;
+/// use telnet_server::test_util::{replay, TraceBuffer};
+///
+/// let trace = TraceBuffer::new();
+/// let mut telnet_session = TelnetSession::create(false);
+/// telnet_session.set_trace(trace.clone());
+/// telnet_session.accept_data(&[255, 253, 1]); // IAC DO ECHO
+///
+/// let mut replayed = TelnetSession::create(false);
+/// replay(&trace.contents(), &mut replayed).unwrap();
+/// assert!(replayed.is_echoing());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TraceBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl TraceBuffer {
+    /// Creates a new, empty `TraceBuffer`
+    pub fn new() -> TraceBuffer {
+        TraceBuffer::default()
+    }
+
+    /// Returns the trace that was recorded so far, e.g. to save it to a file
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().map(|trace| trace.clone()).unwrap_or_default()
+    }
+}
+
+impl Write for TraceBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.0.lock() {
+            Ok(mut trace) => trace.write(buf),
+            Err(_) => Err(std::io::Error::other("Trace buffer is poisoned")),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Line of a trace that couldn't be replayed, see `replay`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MalformedTraceLine {
+    /// Number of the line, starting at 1
+    pub line: usize,
+}
+
+/// Feeds the incoming data of a recorded trace into `session`, chunk by chunk, to
+/// reproduce e.g. a bug exactly. Outgoing data and timestamps of the trace are
+/// ignored, so replaying is deterministic. Responses and events are left to the
+/// caller.
+///
+/// # Arguments
+///
+/// * `trace` - The trace, see `TraceBuffer` and `TelnetSession::set_trace`
+/// * `session` - The session the incoming data is fed into
+///
+/// # Returns
+///
+/// `Err(MalformedTraceLine)` if a line of the trace is malformed. The lines before it
+/// were already replayed.
+pub fn replay(trace: &[u8], session: &mut TelnetSession) -> Result<(), MalformedTraceLine> {
+    for (index, line) in trace.split(|&byte| byte == b'\n').enumerate() {
+        let malformed = MalformedTraceLine { line: index + 1 };
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let line = std::str::from_utf8(line).map_err(|_| malformed)?;
+        let (direction, description) = line
+            .split_once(' ')
+            .and_then(|(_timestamp, rest)| rest.split_once(' '))
+            .ok_or(malformed)?;
+
+        match direction {
+            "<" => {
+                let data = parse_description(description).ok_or(malformed)?;
+                session.accept_data(&data);
+            }
+            ">" => {}
+            _ => return Err(malformed),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telnet::constants::*;
+    use crate::telnet::iac_subneg;
+
+    #[test]
+    fn replayed_trace_should_reproduce_session() {
+        let trace = TraceBuffer::new();
+        let mut session = TelnetSession::create(false);
+        session.set_trace(trace.clone());
+
+        session.accept_data(&[CHAR_IAC, CHAR_DO, CHAR_ECHO, CHAR_IAC, CHAR_WILL, CHAR_NAWS]);
+        session.accept_data(&iac_subneg(CHAR_NAWS, &[0, 80, 0, 24]));
+        session.accept_data(&[CHAR_IAC, CHAR_WILL, CHAR_TERMINAL_TYPE]);
+        session.accept_data(&iac_subneg(CHAR_TERMINAL_TYPE, b"\0VT100"));
+        session.accept_data(b"hi\xFF\xFF\r\n");
+
+        let mut replayed = TelnetSession::create(false);
+        assert_eq!(replay(&trace.contents(), &mut replayed), Ok(()));
+
+        assert!(replayed.is_echoing());
+        assert_eq!(replayed.get_window_size(), session.get_window_size());
+        assert_eq!(replayed.terminal_types(), session.terminal_types());
+        assert_eq!(replayed.data_bytes(), session.data_bytes());
+    }
+
+    #[test]
+    fn malformed_trace_should_be_reported() {
+        let trace = b"0.000001 < IAC DO ECHO\n0.000002 < IAC DO NONSENSE\n";
+        let mut session = TelnetSession::create(false);
+
+        assert_eq!(
+            replay(trace, &mut session),
+            Err(MalformedTraceLine { line: 2 })
+        );
+        assert!(session.is_echoing());
+    }
+}
@@ -0,0 +1,113 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Kills the spawned server binary when the test ends, even if it fails.
+struct ServerProcess(Child);
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        self.0.kill().unwrap_or_default();
+        self.0.wait().unwrap_or_default();
+    }
+}
+
+/// Spawns the server binary, listening on a free port.
+///
+/// # Returns
+///
+/// The spawned process and the address it listens on.
+fn spawn_server() -> (ServerProcess, String) {
+    let address = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+
+    let process = Command::new(env!("CARGO_BIN_EXE_telnet_server"))
+        .env("TELNET_BIND_ADDR", &address)
+        .spawn()
+        .unwrap();
+
+    (ServerProcess(process), address)
+}
+
+fn connect(address: &str) -> TcpStream {
+    /* The server needs some time to bind */
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(address) {
+            return stream;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    panic!("Server at {address} is not reachable");
+}
+
+fn read_until(stream: &mut TcpStream, needle: &[u8]) -> Vec<u8> {
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let mut received = vec![];
+    let mut buffer = [0; 256];
+
+    while !received.windows(needle.len()).any(|w| w == needle) {
+        let read_bytes = stream.read(&mut buffer).unwrap();
+        assert_ne!(read_bytes, 0, "Connection closed unexpectedly");
+        received.extend_from_slice(&buffer[..read_bytes]);
+    }
+
+    received
+}
+
+#[test]
+fn server_should_echo_messages() {
+    let (_server, address) = spawn_server();
+
+    let mut client = connect(&address);
+    client.write_all(b"hello\r\n").unwrap();
+
+    let received = read_until(&mut client, b"\r\n\r\n");
+    assert_eq!(received, b"You sent: hello\r\n\r\n");
+}
+
+#[test]
+fn server_should_respect_bind_address_variable() {
+    let (_server, address) = spawn_server();
+    assert_ne!(address, "127.0.0.1:9000");
+
+    connect(&address);
+}
+
+#[test]
+fn server_should_respect_bind_argument() {
+    let address = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+
+    let _server = ServerProcess(
+        Command::new(env!("CARGO_BIN_EXE_telnet_server"))
+            .args(["--bind", &address])
+            .env("TELNET_BIND_ADDR", "127.0.0.1:1")
+            .spawn()
+            .unwrap(),
+    );
+
+    connect(&address);
+}
+
+#[test]
+fn server_should_reject_malformed_bind_argument() {
+    let status = Command::new(env!("CARGO_BIN_EXE_telnet_server"))
+        .args(["--bind", "not an address"])
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+
+    assert_eq!(status.code(), Some(2));
+}